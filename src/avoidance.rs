@@ -0,0 +1,391 @@
+use bevy::prelude::*;
+
+use crate::components::{ObstacleShape, ROBOT_SIZE};
+use crate::resources::AvoidanceMode;
+use crate::spatial::Neighbour;
+use crate::utilityfunctions::calculate_avoidance_force;
+
+const ORCA_RADIUS: f32 = ROBOT_SIZE / 2.0 + 2.0; // robot radius plus a little margin for the fixed time step
+const TIME_HORIZON: f32 = 1.0; // seconds ahead ORCA keeps clear of other robots
+const WALL_TIME_HORIZON: f32 = 0.25; // seconds ahead ORCA keeps clear of walls
+const EPSILON: f32 = 1e-5;
+
+/// What a robot knows when it picks its velocity for the tick.
+pub struct AvoidanceInput<'a> {
+    pub entity: Entity,
+    pub position: Vec3,
+    pub velocity: Vec3,              // velocity over the last tick
+    pub priority: bool,              // has right of way while recovering from a stall
+    pub goal_dir: Vec3,              // unit direction to the next waypoint, already bent around walls, or zero
+    pub max_speed: f32,
+    pub neighbours: &'a [Neighbour], // robots within `collision_radius` in id order, possibly including this one
+    pub walls: &'a [ObstacleShape],
+    pub collision_radius: f32,
+    pub dt: f32,
+}
+
+/// A way of keeping robots clear of each other. Walls are also kept solid by `push_out_of_walls` afterwards.
+pub trait AvoidanceModel: Send + Sync {
+    /// Velocity the robot drives at this tick, in units per second.
+    fn velocity(&self, input: &AvoidanceInput) -> Vec3;
+}
+
+pub fn avoidance_model(mode: AvoidanceMode) -> &'static dyn AvoidanceModel {
+    match mode {
+        AvoidanceMode::Separation => &Separation,
+        AvoidanceMode::Orca => &Orca,
+    }
+}
+
+// --- SEPARATION ---
+
+/// Boids-style separation from `calculate_avoidance_force`, added to the goal direction at full speed.
+pub struct Separation;
+
+impl AvoidanceModel for Separation {
+    fn velocity(&self, input: &AvoidanceInput) -> Vec3 {
+        let (separation_vector, critical_overlap) = calculate_avoidance_force(
+            input.entity,
+            input.priority,
+            input.position,
+            input.neighbours,
+            input.walls,
+            input.collision_radius
+        );
+
+        let final_direction = if critical_overlap {
+            // Emergency avoidance: Ignore goal, just run away
+            separation_vector
+        } else {
+            // Standard Navigation: Mix goal and avoidance
+            input.goal_dir + separation_vector
+        };
+
+        // We use length_squared() because it's faster (no square root)
+        if final_direction.length_squared() > 0.0001 {
+            final_direction.normalize() * input.max_speed
+        } else {
+            Vec3::ZERO
+        }
+    }
+}
+
+// --- ORCA ---
+
+/// Optimal reciprocal collision avoidance (van den Berg et al.), after the RVO2 library.
+/// Every neighbour rules out the half-plane of velocities that would hit it within `TIME_HORIZON`,
+/// assuming it takes half of the avoiding (all of it if the neighbour stands still or has priority), and every wall
+/// limits how fast the robot may close in on it. The robot then drives at the allowed velocity closest
+/// to its preferred one; if nothing is allowed, at the one that breaks the constraints least.
+pub struct Orca;
+
+/// A half-plane of allowed velocities: those left of `direction` through `point`.
+#[derive(Debug, Clone, Copy)]
+struct Line {
+    point: Vec2,
+    direction: Vec2,
+}
+
+impl AvoidanceModel for Orca {
+    fn velocity(&self, input: &AvoidanceInput) -> Vec3 {
+        let position = input.position.truncate();
+        let velocity = input.velocity.truncate();
+        let mut lines = Vec::with_capacity(input.walls.len() + input.neighbours.len());
+
+        // Walls come first and are never relaxed
+        for wall in input.walls {
+            let (distance, normal) = wall.distance_and_normal(position);
+            if distance > input.collision_radius { continue; }
+            let closing_speed = (distance - ORCA_RADIUS).max(0.0) / WALL_TIME_HORIZON;
+            lines.push(Line { point: normal * -closing_speed, direction: Vec2::new(normal.y, -normal.x) });
+        }
+        let wall_lines = lines.len();
+
+        for other in input.neighbours {
+            if other.entity == input.entity { continue; }
+            lines.push(robot_line(position, velocity, input.priority, other, input.dt));
+        }
+
+        let preferred = input.goal_dir.truncate() * input.max_speed;
+        let mut result = Vec2::ZERO;
+        let failed = linear_program2(&lines, input.max_speed, preferred, false, &mut result);
+        if failed < lines.len() {
+            linear_program3(&lines, wall_lines, failed, input.max_speed, &mut result);
+        }
+        result.extend(0.0)
+    }
+}
+
+/// The ORCA half-plane a neighbour puts on this robot's velocity.
+fn robot_line(position: Vec2, velocity: Vec2, priority: bool, other: &Neighbour, dt: f32) -> Line {
+    let relative_position = other.position.truncate() - position;
+    let relative_velocity = velocity - other.velocity.truncate();
+    let distance_sq = relative_position.length_squared();
+    let combined_radius = ORCA_RADIUS * 2.0;
+    let combined_radius_sq = combined_radius * combined_radius;
+
+    let (direction, u) = if distance_sq > combined_radius_sq {
+        // No overlap yet: the velocity obstacle is a cone truncated at the time horizon
+        let w = relative_velocity - relative_position / TIME_HORIZON;
+        let w_length_sq = w.length_squared();
+        let dot = w.dot(relative_position);
+
+        if dot < 0.0 && dot * dot > combined_radius_sq * w_length_sq {
+            // Closest to the cut-off circle
+            let w_length = w_length_sq.sqrt();
+            let unit_w = w / w_length;
+            (Vec2::new(unit_w.y, -unit_w.x), unit_w * (combined_radius / TIME_HORIZON - w_length))
+        } else {
+            // Closest to one of the legs
+            let leg = (distance_sq - combined_radius_sq).sqrt();
+            let direction = if relative_position.perp_dot(w) > 0.0 {
+                Vec2::new(
+                    relative_position.x * leg - relative_position.y * combined_radius,
+                    relative_position.x * combined_radius + relative_position.y * leg,
+                ) / distance_sq
+            } else {
+                -Vec2::new(
+                    relative_position.x * leg + relative_position.y * combined_radius,
+                    -relative_position.x * combined_radius + relative_position.y * leg,
+                ) / distance_sq
+            };
+            (direction, relative_velocity.dot(direction) * direction - relative_velocity)
+        }
+    } else {
+        // Already overlapping: get apart within the next tick
+        let w = relative_velocity - relative_position / dt;
+        let w_length = w.length();
+        let unit_w = if w_length > EPSILON { w / w_length } else { -relative_position.normalize_or(Vec2::X) };
+        (Vec2::new(unit_w.y, -unit_w.x), unit_w * (combined_radius / dt - w_length))
+    };
+
+    // Robots standing still won't do their half of the avoiding, and robots with priority don't have to
+    let share = if other.velocity == Vec3::ZERO || (other.priority && !priority) { 1.0 } else { 0.5 };
+    Line { point: velocity + u * share, direction }
+}
+
+/// Best velocity on line `line_no` within `radius`, subject to the lines before it. False if there is none.
+fn linear_program1(lines: &[Line], line_no: usize, radius: f32, optimal: Vec2, direction_opt: bool, result: &mut Vec2) -> bool {
+    let line = lines[line_no];
+    let dot = line.point.dot(line.direction);
+    let discriminant = dot * dot + radius * radius - line.point.length_squared();
+    if discriminant < 0.0 {
+        // The line misses the speed circle
+        return false;
+    }
+
+    let root = discriminant.sqrt();
+    let mut t_left = -dot - root;
+    let mut t_right = -dot + root;
+
+    for other in &lines[..line_no] {
+        let denominator = line.direction.perp_dot(other.direction);
+        let numerator = other.direction.perp_dot(line.point - other.point);
+
+        if denominator.abs() <= EPSILON {
+            // Parallel lines: either the other one allows all of this one or none of it
+            if numerator < 0.0 { return false; }
+            continue;
+        }
+
+        let t = numerator / denominator;
+        if denominator >= 0.0 {
+            t_right = t_right.min(t);
+        } else {
+            t_left = t_left.max(t);
+        }
+        if t_left > t_right { return false; }
+    }
+
+    let t = if direction_opt {
+        if optimal.dot(line.direction) > 0.0 { t_right } else { t_left }
+    } else {
+        let t = line.direction.dot(optimal - line.point);
+        if t < t_left { t_left } else if t > t_right { t_right } else { t }
+    };
+    *result = line.point + line.direction * t;
+    true
+}
+
+/// Velocity closest to `optimal` (or furthest along it with `direction_opt`) within `radius` that
+/// satisfies every line. Returns the number of lines, or the index of the first one that couldn't be met.
+fn linear_program2(lines: &[Line], radius: f32, optimal: Vec2, direction_opt: bool, result: &mut Vec2) -> usize {
+    *result = if direction_opt {
+        optimal * radius
+    } else if optimal.length_squared() > radius * radius {
+        optimal.normalize() * radius
+    } else {
+        optimal
+    };
+
+    for (i, line) in lines.iter().enumerate() {
+        if line.direction.perp_dot(line.point - *result) > 0.0 {
+            let previous = *result;
+            if !linear_program1(lines, i, radius, optimal, direction_opt, result) {
+                *result = previous;
+                return i;
+            }
+        }
+    }
+    lines.len()
+}
+
+/// Fallback when the robot lines can't all be met: the velocity that violates them least,
+/// keeping the first `fixed` lines (the walls) as hard constraints.
+fn linear_program3(lines: &[Line], fixed: usize, begin: usize, radius: f32, result: &mut Vec2) {
+    let mut distance = 0.0;
+
+    for i in begin..lines.len() {
+        let line = lines[i];
+        if line.direction.perp_dot(line.point - *result) <= distance { continue; }
+
+        let mut projected: Vec<Line> = lines[..fixed].to_vec();
+        for other in &lines[fixed..i] {
+            let determinant = line.direction.perp_dot(other.direction);
+            let point = if determinant.abs() <= EPSILON {
+                // Parallel lines pointing the same way add nothing
+                if line.direction.dot(other.direction) > 0.0 { continue; }
+                (line.point + other.point) * 0.5
+            } else {
+                line.point + line.direction * (other.direction.perp_dot(line.point - other.point) / determinant)
+            };
+            projected.push(Line { point, direction: (other.direction - line.direction).normalize_or_zero() });
+        }
+
+        let previous = *result;
+        let outward = Vec2::new(-line.direction.y, line.direction.x);
+        if linear_program2(&projected, radius, outward, true, result) < projected.len() {
+            // Only rounding can get here; keep what we had
+            *result = previous;
+        }
+        distance = line.direction.perp_dot(line.point - *result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::RobotId;
+
+    const DT: f32 = 1.0 / 60.0;
+    const SPEED: f32 = 150.0;
+
+    fn neighbour(world: &mut World, position: Vec2, velocity: Vec2) -> Neighbour {
+        Neighbour {
+            id: RobotId(world.entities().len()),
+            entity: world.spawn_empty().id(),
+            position: position.extend(0.0),
+            velocity: velocity.extend(0.0),
+            priority: false,
+        }
+    }
+
+    // What ORCA picks for `robot`, driving at its current velocity towards `goal`
+    fn orca(robot: &Neighbour, goal: Vec2, neighbours: &[Neighbour], walls: &[ObstacleShape]) -> Vec2 {
+        Orca.velocity(&AvoidanceInput {
+            entity: robot.entity,
+            position: robot.position,
+            velocity: robot.velocity,
+            priority: robot.priority,
+            goal_dir: (goal - robot.position.truncate()).normalize().extend(0.0),
+            max_speed: SPEED,
+            neighbours,
+            walls,
+            collision_radius: 80.0,
+            dt: DT,
+        }).truncate()
+    }
+
+    // Closest the two get within the time horizon, each keeping its velocity
+    fn closest_approach(a: Vec2, a_velocity: Vec2, b: Vec2, b_velocity: Vec2) -> f32 {
+        (0..=100).map(|step| {
+            let t = TIME_HORIZON * step as f32 / 100.0;
+            (a + a_velocity * t).distance(b + b_velocity * t)
+        }).fold(f32::INFINITY, f32::min)
+    }
+
+    // Whether `velocity` is on the allowed side of `line`
+    fn allows(line: &Line, velocity: Vec2) -> bool {
+        line.direction.perp_dot(line.point - velocity) <= EPSILON
+    }
+
+    #[test]
+    fn head_on_robots_pass_each_other() {
+        let mut world = World::new();
+        let left = neighbour(&mut world, Vec2::new(-100.0, 0.0), Vec2::new(SPEED, 0.0));
+        let right = neighbour(&mut world, Vec2::new(100.0, 0.0), Vec2::new(-SPEED, 0.0));
+        let both = [left, right];
+
+        let left_velocity = orca(&left, Vec2::new(300.0, 0.0), &both, &[]);
+        let right_velocity = orca(&right, Vec2::new(-300.0, 0.0), &both, &[]);
+
+        // Driving straight on they would meet in well under a second
+        assert!(closest_approach(left.position.truncate(), Vec2::new(SPEED, 0.0), right.position.truncate(), Vec2::new(-SPEED, 0.0)) < ORCA_RADIUS);
+        let closest = closest_approach(left.position.truncate(), left_velocity, right.position.truncate(), right_velocity);
+        assert!(closest >= ORCA_RADIUS * 2.0 - 0.01, "{left_velocity} and {right_velocity} come {closest} apart");
+        // Each does half of the avoiding and still makes progress
+        assert!((left_velocity.y + right_velocity.y).abs() < 0.01);
+        assert!(left_velocity.x > 0.0 && right_velocity.x < 0.0);
+    }
+
+    #[test]
+    fn a_robot_standing_still_leaves_all_the_avoiding_to_the_mover() {
+        let mut world = World::new();
+        let mover = neighbour(&mut world, Vec2::new(-100.0, 5.0), Vec2::new(SPEED, 0.0));
+        let parked = neighbour(&mut world, Vec2::new(100.0, 0.0), Vec2::ZERO);
+
+        let velocity = orca(&mover, Vec2::new(300.0, 5.0), &[mover, parked], &[]);
+
+        let closest = closest_approach(mover.position.truncate(), velocity, parked.position.truncate(), Vec2::ZERO);
+        assert!(closest >= ORCA_RADIUS * 2.0 - 0.01, "{velocity} comes {closest} from the parked robot");
+    }
+
+    #[test]
+    fn a_robot_with_priority_leaves_all_the_avoiding_to_the_other() {
+        let mut world = World::new();
+        let mover = neighbour(&mut world, Vec2::new(-100.0, 5.0), Vec2::new(SPEED, 0.0));
+        let other = neighbour(&mut world, Vec2::new(100.0, 0.0), Vec2::new(-SPEED, 0.0));
+        let velocity = mover.velocity.truncate();
+
+        let shared = robot_line(mover.position.truncate(), velocity, false, &other, DT);
+        let leading = robot_line(mover.position.truncate(), velocity, false, &Neighbour { priority: true, ..other }, DT);
+
+        // Same half-plane edge, moved twice as far from the current velocity
+        assert_eq!(shared.direction, leading.direction);
+        assert!(((leading.point - velocity) - (shared.point - velocity) * 2.0).length() < 0.01);
+    }
+
+    #[test]
+    fn walls_hold_when_the_robot_lines_cannot_all_be_met() {
+        // A wall below that allows no downward speed, and two robot lines asking for opposite things
+        let lines = [
+            Line { point: Vec2::ZERO, direction: Vec2::X },                  // y >= 0
+            Line { point: Vec2::new(0.0, -50.0), direction: Vec2::NEG_X },   // y <= -50
+            Line { point: Vec2::new(60.0, 0.0), direction: Vec2::Y },       // x <= 60
+            Line { point: Vec2::new(100.0, 0.0), direction: Vec2::NEG_Y },   // x >= 100
+        ];
+        let mut result = Vec2::ZERO;
+        let failed = linear_program2(&lines, SPEED, Vec2::new(SPEED, 0.0), false, &mut result);
+        assert!(failed < lines.len());
+
+        linear_program3(&lines, 1, failed, SPEED, &mut result);
+        assert!(allows(&lines[0], result), "{result} breaks the wall");
+        assert!(result.length() <= SPEED + 0.01);
+        // The robot lines are broken as little as the wall allows: y = 0 misses the first by 50, and no x misses the others by less
+        let worst = lines[1..].iter().map(|line| line.direction.perp_dot(line.point - result)).fold(f32::MIN, f32::max);
+        assert!((worst - 50.0).abs() < 0.01, "{result} misses a robot line by {worst}");
+    }
+
+    #[test]
+    fn a_robot_pinned_against_a_wall_is_not_pushed_into_it() {
+        let mut world = World::new();
+        // A wall along y = 0, the robot touching it from above and a neighbour closing in from above
+        let wall = ObstacleShape::Segment { start: Vec2::new(-500.0, 0.0), end: Vec2::new(500.0, 0.0), half_thickness: 10.0 };
+        let pinned = neighbour(&mut world, Vec2::new(0.0, 10.0 + ORCA_RADIUS), Vec2::ZERO);
+        let pusher = neighbour(&mut world, Vec2::new(0.0, 10.0 + ORCA_RADIUS * 2.5), Vec2::new(0.0, -SPEED));
+
+        let velocity = orca(&pinned, Vec2::new(0.0, -300.0), &[pinned, pusher], &[wall]);
+
+        assert!(velocity.y >= -0.01, "{velocity} drives into the wall");
+    }
+}
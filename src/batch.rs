@@ -0,0 +1,177 @@
+use bevy::prelude::*;
+use serde::Serialize;
+use std::num::NonZero;
+
+use crate::components::{RobotId, RobotState};
+use crate::metrics::{Metrics, MetricsReport};
+use crate::resources::RunSettings;
+use crate::utilityfunctions::state_checksum;
+
+/// Why a run stopped. Each outcome maps to the process exit status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum RunOutcome {
+    DurationReached,   // ran for the requested simulated time
+    DeliveriesReached, // completed the requested number of deliveries
+    TargetMissed,      // ran out of time before reaching the delivery target
+    FleetDead,         // every robot ran out of battery
+}
+
+impl RunOutcome {
+    pub fn exit_code(self) -> AppExit {
+        match self {
+            RunOutcome::DurationReached | RunOutcome::DeliveriesReached => AppExit::Success,
+            RunOutcome::TargetMissed => AppExit::Error(NonZero::new(2).unwrap()),
+            RunOutcome::FleetDead => AppExit::Error(NonZero::new(3).unwrap()),
+        }
+    }
+}
+
+/// Written to `<output>/summary.ron` when a run ends.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunSummary {
+    pub outcome: RunOutcome,
+    pub seed: u64,
+    pub sim_time_secs: f32,
+    pub wall_time_secs: f32,
+    pub deliveries_completed: u64,
+    pub robots_alive: usize,
+    pub robots_dead: usize,
+    pub checksum: String, // identical for two deterministic runs with the same scenario and seed
+    pub metrics: MetricsReport,
+}
+
+// --- STOP CONDITIONS ---
+
+/// Ends the run once the simulated duration or delivery target is reached, or the whole fleet is dead.
+pub fn check_run_end(
+    time: Res<Time>,
+    real_time: Res<Time<Real>>,
+    settings: Res<RunSettings>,
+    metrics: Res<Metrics>,
+    robots: Query<(&RobotId, &Transform, &RobotState)>,
+    mut exit: MessageWriter<AppExit>,
+    mut finished: Local<bool>
+) {
+    // Several fixed ticks can run in the frame that requested the exit
+    if *finished { return; }
+
+    let sim_time = time.elapsed_secs();
+    let robots_dead = robots.iter().filter(|(_, _, state)| **state == RobotState::Dead).count();
+    let robots_alive = robots.iter().len() - robots_dead;
+
+    let deliveries_reached = settings.target_deliveries
+        .is_some_and(|target| metrics.deliveries_completed >= target);
+    let duration_reached = settings.duration.is_some_and(|duration| sim_time >= duration);
+
+    let outcome = if deliveries_reached {
+        RunOutcome::DeliveriesReached
+    } else if robots_alive == 0 && robots_dead > 0 {
+        RunOutcome::FleetDead
+    } else if duration_reached && settings.target_deliveries.is_some() {
+        RunOutcome::TargetMissed
+    } else if duration_reached {
+        RunOutcome::DurationReached
+    } else {
+        return;
+    };
+    *finished = true;
+
+    let checksum = state_checksum(robots.iter().map(|(id, t, state)| (id.0, t.translation, *state as u8)));
+    let summary = RunSummary {
+        outcome,
+        seed: settings.seed,
+        sim_time_secs: sim_time,
+        wall_time_secs: real_time.startup().elapsed().as_secs_f32(), // true wall clock, even when ticks are driven manually
+        deliveries_completed: metrics.deliveries_completed,
+        robots_alive,
+        robots_dead,
+        checksum: format!("{checksum:016x}"),
+        metrics: metrics.report(),
+    };
+
+    println!("🏁 Run finished: {:?} after {:.1}s simulated ({:.1}s wall clock)", outcome, sim_time, summary.wall_time_secs);
+    println!("   Deliveries: {}, robots alive: {}, dead: {}", summary.deliveries_completed, robots_alive, robots_dead);
+    println!("   Final state checksum: {}", summary.checksum);
+    print!("{}", summary.metrics);
+
+    if let Err(err) = write_summary(&settings, &summary) {
+        eprintln!("error: failed to write run summary: {err}");
+    }
+    exit.write(outcome.exit_code());
+}
+
+fn write_summary(settings: &RunSettings, summary: &RunSummary) -> std::io::Result<()> {
+    std::fs::create_dir_all(&settings.output_dir)?;
+    let pretty = ron::ser::PrettyConfig::default();
+    let serialized = ron::ser::to_string_pretty(summary, pretty).map_err(std::io::Error::other)?;
+    std::fs::write(settings.output_dir.join("summary.ron"), serialized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::message::Messages;
+    use std::time::Duration;
+
+    // Runs the stop check once, `sim_time` seconds in, and returns the exit it asked for and the summary it wrote
+    fn check(name: &str, duration: Option<f32>, target: Option<u64>, delivered: u64, states: &[RobotState], sim_time: f32) -> (Vec<AppExit>, Option<String>) {
+        let output_dir = std::env::temp_dir().join(format!("swarm_batch_{name}_{}", std::process::id()));
+        let mut app = App::new();
+        app.insert_resource(Time::<()>::default())
+            .insert_resource(Time::<Real>::default())
+            .insert_resource(RunSettings { duration, target_deliveries: target, seed: 7, output_dir: output_dir.clone() })
+            .insert_resource(Metrics { deliveries_completed: delivered, ..default() })
+            .add_message::<AppExit>()
+            .add_systems(Update, check_run_end);
+        for (id, state) in states.iter().enumerate() {
+            app.world_mut().spawn((RobotId(id as u32), Transform::default(), *state));
+        }
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs_f32(sim_time));
+        app.update();
+
+        let exits = app.world_mut().resource_mut::<Messages<AppExit>>().drain().collect();
+        let summary = std::fs::read_to_string(output_dir.join("summary.ron")).ok();
+        let _ = std::fs::remove_dir_all(&output_dir);
+        (exits, summary)
+    }
+
+    fn outcome(summary: &str) -> &str {
+        summary.lines().find_map(|line| line.trim().strip_prefix("outcome: ")).unwrap().trim_end_matches(',')
+    }
+
+    #[test]
+    fn only_a_missed_target_or_a_dead_fleet_fail() {
+        assert_eq!(RunOutcome::DurationReached.exit_code(), AppExit::Success);
+        assert_eq!(RunOutcome::DeliveriesReached.exit_code(), AppExit::Success);
+        assert_eq!(RunOutcome::TargetMissed.exit_code(), AppExit::Error(NonZero::new(2).unwrap()));
+        assert_eq!(RunOutcome::FleetDead.exit_code(), AppExit::Error(NonZero::new(3).unwrap()));
+    }
+
+    #[test]
+    fn running_out_of_time_short_of_the_target_misses_it() {
+        let (exits, summary) = check("missed", Some(60.0), Some(10), 9, &[RobotState::Idle], 60.0);
+        assert_eq!(exits, [AppExit::Error(NonZero::new(2).unwrap())]);
+        assert_eq!(outcome(&summary.unwrap()), "TargetMissed");
+    }
+
+    #[test]
+    fn reaching_the_target_ends_the_run_early() {
+        let (exits, summary) = check("reached", Some(60.0), Some(10), 10, &[RobotState::Idle], 30.0);
+        assert_eq!(exits, [AppExit::Success]);
+        assert_eq!(outcome(&summary.unwrap()), "DeliveriesReached");
+    }
+
+    #[test]
+    fn a_dead_fleet_ends_the_run() {
+        let (exits, summary) = check("dead", None, None, 0, &[RobotState::Dead, RobotState::Dead], 30.0);
+        assert_eq!(exits, [AppExit::Error(NonZero::new(3).unwrap())]);
+        assert_eq!(outcome(&summary.unwrap()), "FleetDead");
+    }
+
+    #[test]
+    fn a_run_with_time_left_goes_on() {
+        let (exits, summary) = check("going", Some(60.0), None, 0, &[RobotState::Dead, RobotState::Idle], 30.0);
+        assert_eq!(exits, []);
+        assert_eq!(summary, None);
+    }
+}
@@ -0,0 +1,289 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::resources::SimulationConfig;
+
+pub const USAGE: &str = "\
+Usage: bevy_ecs_sim [OPTIONS]
+
+Options:
+  -s, --scenario <PATH>     Scenario file to load (default: assets/simulation.ron)
+  -n, --robot-count <N>     Override the robot count from the scenario
+  -d, --duration <SECS>     Stop after this many simulated seconds
+      --deliveries <N>      Stop after this many completed deliveries
+      --batch               Fast-forward headless run that writes a summary and exits
+                            (needs --duration and/or --deliveries, implies --deterministic)
+      --seed <SEED>         Seed for the simulation (default: 0)
+      --deterministic       Advance one fixed tick per frame for reproducible runs (disables hot-reload)
+  -o, --output <DIR>        Directory for run output (default: output)
+      --sample-interval <SECS>
+                            Export robot and KPI samples every SECS simulated seconds
+      --no-event-log        Do not append robot state changes to <DIR>/events.jsonl
+      --record <PATH>       Record every tick to a replay file
+      --replay <PATH>       Play back a recording instead of simulating
+      --seek <SECS>         Start the replay at this simulated time (with --replay)
+      --headless            Run without a window
+      --visual              Run with a window (not available in headless builds)
+  -h, --help                Print this help and exit";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunMode {
+    Visual,
+    Headless,
+}
+
+impl Default for RunMode {
+    fn default() -> Self {
+        if cfg!(feature = "headless") { RunMode::Headless } else { RunMode::Visual }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CliArgs {
+    pub scenario: PathBuf,
+    pub robot_count: Option<usize>,
+    pub duration: Option<f32>,
+    pub target_deliveries: Option<u64>,
+    pub batch: bool,
+    pub seed: u64,
+    pub deterministic: bool,
+    pub output_dir: PathBuf,
+    pub sample_interval: Option<f32>,
+    pub event_log: bool,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub seek: Option<f32>,
+    pub mode: RunMode,
+}
+
+impl Default for CliArgs {
+    fn default() -> Self {
+        Self {
+            scenario: PathBuf::from("assets/simulation.ron"),
+            robot_count: None,
+            duration: None,
+            target_deliveries: None,
+            batch: false,
+            seed: 0,
+            deterministic: false,
+            output_dir: PathBuf::from("output"),
+            sample_interval: None,
+            event_log: true,
+            record: None,
+            replay: None,
+            seek: None,
+            mode: RunMode::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CliError {
+    HelpRequested,
+    MissingValue(String),
+    InvalidValue { flag: String, value: String },
+    UnknownArgument(String),
+    VisualUnavailable,
+    BatchWithoutEnd,
+    BatchIsHeadless,
+    SeekWithoutReplay,
+    ReplayConflict(&'static str),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::HelpRequested => write!(f, "{USAGE}"),
+            CliError::MissingValue(flag) => write!(f, "missing value for '{flag}'"),
+            CliError::InvalidValue { flag, value } => write!(f, "invalid value '{value}' for '{flag}'"),
+            CliError::UnknownArgument(arg) => write!(f, "unknown argument '{arg}'"),
+            CliError::VisualUnavailable => write!(f, "'--visual' is not available in a build with the 'headless' feature"),
+            CliError::BatchWithoutEnd => write!(f, "'--batch' needs '--duration' and/or '--deliveries' to know when to stop"),
+            CliError::BatchIsHeadless => write!(f, "'--batch' always runs headless and cannot be combined with '--visual'"),
+            CliError::SeekWithoutReplay => write!(f, "'--seek' only applies to '--replay'"),
+            CliError::ReplayConflict(flag) => write!(f, "'--replay' plays back a finished run and cannot be combined with '{flag}'"),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+impl CliArgs {
+    /// Parses the process arguments (skipping the binary name).
+    pub fn parse() -> Result<Self, CliError> {
+        Self::parse_from(std::env::args().skip(1))
+    }
+
+    pub fn parse_from<I>(args: I) -> Result<Self, CliError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut parsed = CliArgs::default();
+        let mut args = args.into_iter();
+        let mut visual_requested = false;
+
+        while let Some(arg) = args.next() {
+            // Accept both "--flag value" and "--flag=value"
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+                _ => (arg.clone(), None),
+            };
+            let mut value = || match inline_value.clone() {
+                Some(value) => Ok(value),
+                None => args.next().ok_or_else(|| CliError::MissingValue(flag.clone())),
+            };
+
+            match flag.as_str() {
+                "-h" | "--help" => return Err(CliError::HelpRequested),
+                "-s" | "--scenario" => parsed.scenario = PathBuf::from(value()?),
+                "-n" | "--robot-count" => {
+                    let raw = value()?;
+                    let count: usize = parse_value(&flag, raw.clone())?;
+                    if count == 0 {
+                        return Err(CliError::InvalidValue { flag, value: raw });
+                    }
+                    parsed.robot_count = Some(count);
+                }
+                "-d" | "--duration" => {
+                    let raw = value()?;
+                    let secs: f32 = parse_value(&flag, raw.clone())?;
+                    if !secs.is_finite() || secs <= 0.0 {
+                        return Err(CliError::InvalidValue { flag, value: raw });
+                    }
+                    parsed.duration = Some(secs);
+                }
+                "--deliveries" => {
+                    let raw = value()?;
+                    let count: u64 = parse_value(&flag, raw.clone())?;
+                    if count == 0 {
+                        return Err(CliError::InvalidValue { flag, value: raw });
+                    }
+                    parsed.target_deliveries = Some(count);
+                }
+                "--batch" => parsed.batch = true,
+                "--seed" => parsed.seed = parse_value(&flag, value()?)?,
+                "--deterministic" => parsed.deterministic = true,
+                "-o" | "--output" => parsed.output_dir = PathBuf::from(value()?),
+                "--sample-interval" => {
+                    let raw = value()?;
+                    let secs: f32 = parse_value(&flag, raw.clone())?;
+                    if !secs.is_finite() || secs <= 0.0 {
+                        return Err(CliError::InvalidValue { flag, value: raw });
+                    }
+                    parsed.sample_interval = Some(secs);
+                }
+                "--no-event-log" => parsed.event_log = false,
+                "--record" => parsed.record = Some(PathBuf::from(value()?)),
+                "--replay" => parsed.replay = Some(PathBuf::from(value()?)),
+                "--seek" => {
+                    let raw = value()?;
+                    let secs: f32 = parse_value(&flag, raw.clone())?;
+                    if !secs.is_finite() || secs < 0.0 {
+                        return Err(CliError::InvalidValue { flag, value: raw });
+                    }
+                    parsed.seek = Some(secs);
+                }
+                "--headless" => parsed.mode = RunMode::Headless,
+                "--visual" => {
+                    if cfg!(feature = "headless") {
+                        return Err(CliError::VisualUnavailable);
+                    }
+                    parsed.mode = RunMode::Visual;
+                    visual_requested = true;
+                }
+                _ => return Err(CliError::UnknownArgument(arg)),
+            }
+        }
+
+        // A replay runs none of the simulation, so options that drive or observe it don't apply
+        if parsed.replay.is_some() {
+            for (set, flag) in [
+                (parsed.batch, "--batch"),
+                (parsed.record.is_some(), "--record"),
+                (parsed.sample_interval.is_some(), "--sample-interval"),
+                (parsed.target_deliveries.is_some(), "--deliveries"),
+            ] {
+                if set {
+                    return Err(CliError::ReplayConflict(flag));
+                }
+            }
+        } else if parsed.seek.is_some() {
+            return Err(CliError::SeekWithoutReplay);
+        }
+
+        // Batch runs are unattended, so they must end on their own and never open a window
+        if parsed.batch {
+            if parsed.duration.is_none() && parsed.target_deliveries.is_none() {
+                return Err(CliError::BatchWithoutEnd);
+            }
+            if visual_requested {
+                return Err(CliError::BatchIsHeadless);
+            }
+            parsed.mode = RunMode::Headless;
+            parsed.deterministic = true;
+        }
+
+        Ok(parsed)
+    }
+
+    /// Applies command-line overrides on top of a loaded scenario.
+    pub fn apply_overrides(&self, config: &mut SimulationConfig) {
+        if let Some(count) = self.robot_count {
+            config.robot_count = count;
+        }
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: String) -> Result<T, CliError> {
+    value.parse().map_err(|_| CliError::InvalidValue { flag: flag.to_string(), value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<CliArgs, CliError> {
+        CliArgs::parse_from(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn rejects_zero_robots() {
+        for args in [&["-n", "0"][..], &["--robot-count=0"][..]] {
+            match parse(args) {
+                Err(CliError::InvalidValue { value, .. }) => assert_eq!(value, "0"),
+                other => panic!("expected an invalid value for {args:?}, got {other:?}"),
+            }
+        }
+        assert_eq!(parse(&["-n", "12"]).unwrap().robot_count, Some(12));
+    }
+
+    #[test]
+    fn batch_implies_headless_and_deterministic() {
+        let args = parse(&["--batch", "--duration", "60"]).unwrap();
+        assert!(args.batch);
+        assert!(args.deterministic);
+        assert_eq!(args.mode, RunMode::Headless);
+        assert_eq!(args.duration, Some(60.0));
+    }
+
+    #[test]
+    fn batch_needs_an_end() {
+        assert!(matches!(parse(&["--batch"]), Err(CliError::BatchWithoutEnd)));
+    }
+
+    #[test]
+    fn seek_needs_replay() {
+        assert!(matches!(parse(&["--seek", "5"]), Err(CliError::SeekWithoutReplay)));
+        let args = parse(&["--replay", "run.rec", "--seek", "5"]).unwrap();
+        assert_eq!(args.seek, Some(5.0));
+    }
+
+    #[test]
+    fn rejects_unknown_flags() {
+        match parse(&["--duration", "10", "--frobnicate"]) {
+            Err(CliError::UnknownArgument(arg)) => assert_eq!(arg, "--frobnicate"),
+            other => panic!("expected an unknown argument, got {other:?}"),
+        }
+        assert!(matches!(parse(&["--seed"]), Err(CliError::MissingValue(_))));
+    }
+}
@@ -0,0 +1,135 @@
+use bevy::platform::collections::HashSet;
+use bevy::prelude::*;
+use serde::Serialize;
+use std::collections::VecDeque;
+
+use crate::components::*;
+use crate::spatial::{Neighbour, SpatialGrid};
+
+const WALL_TOLERANCE: f32 = 0.01; // push_out_of_walls leaves robots touching walls, that isn't a collision
+const KEPT_INCIDENTS: usize = 1000; // latest incidents kept in the CollisionLog; collisions.jsonl has them all
+
+/// What a robot ran into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub enum Collider {
+    Robot(RobotId),
+    Obstacle(Entity), // the rack or wall, whichever of its boxes or segments was hit
+}
+
+/// Sent when two bodies start to overlap. One message per contact, however many ticks it lasts.
+#[derive(Message, Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct CollisionDetected {
+    pub robot: RobotId,
+    pub other: Collider,      // for robot-robot contacts, the robot with the higher id
+    pub position: (f32, f32), // where the bodies touch
+    pub depth: f32,           // how far they overlap
+    pub sim_time: f32,
+}
+
+/// How many collisions there were, the latest ones, and the contacts still going on.
+#[derive(Resource, Debug, Default)]
+pub struct CollisionLog {
+    pub total: u64,
+    pub incidents: VecDeque<CollisionDetected>, // the latest KEPT_INCIDENTS, oldest first
+    contacts: HashSet<(RobotId, Collider)>,
+}
+
+// --- SYSTEM ---
+
+/// Checks where the robots ended up after moving for robots overlapping each other (by their `Footprint`)
+/// or an obstacle, and records a `CollisionDetected` for each contact that wasn't there last tick.
+pub fn detect_collisions(
+    time: Res<Time>,
+    mut log: ResMut<CollisionLog>,
+    mut collisions: MessageWriter<CollisionDetected>,
+    robot_query: Query<(Entity, &RobotId, &Transform, &Footprint), With<Robot>>,
+    wall_query: Query<(&ObstacleShape, &ChildOf)>,
+    mut grid: Local<SpatialGrid>,
+    mut contacts: Local<Vec<(RobotId, Collider, Vec2, f32)>>
+)
+{
+    // Bodies can only touch within two of the largest footprints
+    let reach = robot_query.iter().map(|(.., footprint)| footprint.0).fold(0.0, f32::max) * 2.0;
+    if reach <= 0.0 { return; }
+    grid.clear(reach);
+    for (entity, id, transform, _) in &robot_query {
+        grid.insert(Neighbour { id: *id, entity, position: transform.translation, velocity: Vec3::ZERO, priority: false });
+    }
+
+    contacts.clear();
+    for (_, &id, transform, footprint) in &robot_query {
+        let (position, radius) = (transform.translation, footprint.0);
+        for other in grid.nearby(position, reach) {
+            if other.id <= id { continue; }
+            let Ok((.., other_footprint)) = robot_query.get(other.entity) else { continue };
+            let distance = position.distance(other.position);
+            let depth = radius + other_footprint.0 - distance;
+            if depth > 0.0 {
+                let contact = position.truncate().lerp(other.position.truncate(), radius / (radius + other_footprint.0));
+                contacts.push((id, Collider::Robot(other.id), contact, depth));
+            }
+        }
+
+        for (wall, obstacle) in &wall_query {
+            let (distance, normal) = wall.distance_and_normal(position.truncate());
+            let depth = radius - distance;
+            if depth > WALL_TOLERANCE {
+                contacts.push((id, Collider::Obstacle(obstacle.parent()), position.truncate() - normal * distance, depth));
+            }
+        }
+    }
+
+    // Only contacts that weren't there last tick are new incidents. Robots and their neighbours
+    // came in archetype and cell order, so put the contacts in id order first.
+    // A robot in a corner touches two parts of the same wall: that is one contact, the deeper one
+    contacts.sort_by(|(id, other, _, depth), (other_id, other_other, _, other_depth)| {
+        (id, other).cmp(&(other_id, other_other)).then(other_depth.total_cmp(depth))
+    });
+    contacts.dedup_by_key(|(id, other, ..)| (*id, *other));
+    let mut ongoing = HashSet::with_capacity(contacts.len());
+    for &(robot, other, position, depth) in contacts.iter() {
+        ongoing.insert((robot, other));
+        if log.contacts.contains(&(robot, other)) { continue; }
+
+        let incident = CollisionDetected { robot, other, position: (position.x, position.y), depth, sim_time: time.elapsed_secs() };
+        log.total += 1;
+        if log.incidents.len() == KEPT_INCIDENTS {
+            log.incidents.pop_front();
+        }
+        log.incidents.push_back(incident);
+        collisions.write(incident);
+    }
+    log.contacts = ongoing;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::message::Messages;
+
+    #[test]
+    fn a_corner_is_one_contact_with_its_wall() {
+        let mut app = App::new();
+        app.insert_resource(Time::<()>::default())
+            .init_resource::<CollisionLog>()
+            .add_message::<CollisionDetected>()
+            .add_systems(Update, detect_collisions);
+
+        // An L-shaped wall, and a robot wedged into its corner
+        let wall = app.world_mut().spawn(Obstacle).id();
+        for end in [Vec2::new(100.0, 0.0), Vec2::new(0.0, 100.0)] {
+            app.world_mut().spawn((ObstacleShape::Segment { start: Vec2::ZERO, end, half_thickness: 5.0 }, ChildOf(wall)));
+        }
+        app.world_mut().spawn((Robot, RobotId(0), Transform::from_xyz(10.0, 10.0, 0.0), Footprint(15.0)));
+
+        app.update();
+        app.update();
+
+        let log = app.world().resource::<CollisionLog>();
+        assert_eq!(log.total, 1, "one contact, however many ticks it lasts");
+        assert_eq!(log.incidents.len(), 1);
+        assert_eq!(log.incidents[0].other, Collider::Obstacle(wall));
+        let messages: Vec<CollisionDetected> = app.world_mut().resource_mut::<Messages<CollisionDetected>>().drain().collect();
+        assert_eq!(messages.len(), 1);
+    }
+}
@@ -1,7 +1,14 @@
 use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+// --- SIZES ---
+pub const ROBOT_SIZE: f32 = 30.0;
+pub const STATION_SIZE: f32 = 40.0;  // Pickup & Dropoff footprint
+pub const CHARGER_SIZE: f32 = 50.0;
 
 // --- STATES ---
-#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum RobotState {
     Idle,
     MovingToPickup,
@@ -13,6 +20,76 @@ pub enum RobotState {
     MovingToCharger,
     Charging,
     Dead,
+    MovingToPark, // nothing to do: heading back to the spawn slot, off the stations (last so recorded indexes stay valid)
+    QueuedForDropoff, // in line for a taken dropoff, at a queue spot or moving up
+    QueuedForCharger,
+}
+
+impl RobotState {
+    pub const ALL: [RobotState; 13] = [
+        RobotState::Idle,
+        RobotState::MovingToPickup,
+        RobotState::PickingUp,
+        RobotState::WaitingForDropoff,
+        RobotState::MovingToDropoff,
+        RobotState::DroppingOff,
+        RobotState::WaitingForCharger,
+        RobotState::MovingToCharger,
+        RobotState::Charging,
+        RobotState::Dead,
+        RobotState::MovingToPark,
+        RobotState::QueuedForDropoff,
+        RobotState::QueuedForCharger,
+    ];
+
+    // Position in RobotState::ALL, for per-state arrays
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// States where the robot is doing delivery work.
+    pub fn is_productive(self) -> bool {
+        matches!(self,
+            RobotState::MovingToPickup |
+            RobotState::PickingUp |
+            RobotState::MovingToDropoff |
+            RobotState::DroppingOff
+        )
+    }
+
+    /// States where the robot drives to a target.
+    pub fn is_moving(self) -> bool {
+        matches!(self,
+            RobotState::MovingToPickup |
+            RobotState::MovingToDropoff |
+            RobotState::MovingToCharger |
+            RobotState::MovingToPark |
+            RobotState::QueuedForDropoff |
+            RobotState::QueuedForCharger
+        )
+    }
+
+    /// States where the robot is in line for a station. It drives like a moving robot, but stands still once at its spot.
+    pub fn is_queued(self) -> bool {
+        matches!(self, RobotState::QueuedForDropoff | RobotState::QueuedForCharger)
+    }
+
+    /// The state a robot falls back to when the station it was heading to or working at disappears.
+    pub fn without_station(self) -> RobotState {
+        match self {
+            RobotState::MovingToPickup | RobotState::PickingUp => RobotState::Idle,
+            RobotState::MovingToDropoff | RobotState::DroppingOff | RobotState::QueuedForDropoff => RobotState::WaitingForDropoff,
+            RobotState::MovingToCharger | RobotState::Charging | RobotState::QueuedForCharger => RobotState::WaitingForCharger,
+            other => other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StationKind {
+    Pickup,
+    Dropoff,
+    Charger,
 }
 
 // --- TAGS ---
@@ -28,6 +105,23 @@ pub struct DropoffStation;
 #[derive(Component)]
 pub struct ChargerStation;
 
+// Query filters for one kind of station, disjoint from the other kinds so their queries can borrow mutably side by side
+pub type OnlyPickups = (With<PickupStation>, Without<DropoffStation>, Without<ChargerStation>);
+pub type OnlyDropoffs = (With<DropoffStation>, Without<PickupStation>, Without<ChargerStation>);
+pub type OnlyChargers = (With<ChargerStation>, Without<PickupStation>, Without<DropoffStation>);
+
+// One per rack or wall, with a child `ObstacleShape` for each of its boxes or segments
+#[derive(Component)]
+pub struct Obstacle;
+
+// --- IDS ---
+// Stable identifiers, used to iterate in the same order on every run regardless of archetype layout
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct RobotId(pub u32);
+
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct StationId(pub u32);
+
 // --- DATA ---
 #[derive(Component)]
 pub struct Speed(pub f32);
@@ -37,18 +131,440 @@ pub struct TargetPosition(pub Vec3);
 
 #[derive(Component)]
 pub struct RobotTimers {
-    pub work: Timer,   // For Picking Up / Dropping Off (1.0s)
-    pub charge: Timer, // For Charging (variable from config)
+    pub work: Timer, // For Picking Up / Dropping Off (sampled from the station's ServiceTime each visit)
 }
 
-#[derive(Component)]
-pub struct Booked(pub bool);
+/// The bays of a station, where robots dock to work at it, and the robot booked into each.
+#[derive(Component, Debug, Default)]
+pub struct Bays {
+    pub docks: Vec<Vec3>,               // where a robot in each bay stands
+    pub occupants: Vec<Option<Entity>>, // robot booked into each bay, same order
+}
+
+impl Bays {
+    pub fn new(docks: Vec<Vec3>) -> Self {
+        let occupants = vec![None; docks.len()];
+        Self { docks, occupants }
+    }
+
+    /// How many bays nobody has booked.
+    pub fn free(&self) -> usize {
+        self.occupants.iter().filter(|occupant| occupant.is_none()).count()
+    }
+
+    /// Where the free bays are.
+    pub fn free_docks(&self) -> impl Iterator<Item = Vec3> + '_ {
+        self.docks.iter().zip(&self.occupants).filter(|(_, occupant)| occupant.is_none()).map(|(dock, _)| *dock)
+    }
+
+    /// Books the free bay closest to `from` for `robot` and returns where it docks.
+    /// A robot that holds a bay here already keeps it.
+    pub fn book(&mut self, robot: Entity, from: Vec3) -> Option<Vec3> {
+        if let Some(bay) = self.occupants.iter().position(|&occupant| occupant == Some(robot)) {
+            return Some(self.docks[bay]);
+        }
+        let bay = (0..self.docks.len())
+            .filter(|&bay| self.occupants[bay].is_none())
+            .min_by(|&a, &b| from.distance(self.docks[a]).total_cmp(&from.distance(self.docks[b])))?;
+        self.occupants[bay] = Some(robot);
+        Some(self.docks[bay])
+    }
+
+    /// Frees the bay `robot` holds, if any.
+    pub fn release(&mut self, robot: Entity) {
+        for occupant in &mut self.occupants {
+            if *occupant == Some(robot) {
+                *occupant = None;
+            }
+        }
+    }
+}
+
+/// How long a pickup or dropoff takes to serve a robot, in simulated seconds, drawn anew for every visit.
+/// Draws below zero count as zero.
+#[derive(Component, Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum ServiceTime {
+    Constant(f32),
+    Uniform { min: f32, max: f32 },
+    Normal { mean: f32, std_dev: f32 },
+    Exponential { mean: f32 },
+}
+
+impl Default for ServiceTime {
+    fn default() -> Self {
+        ServiceTime::Constant(1.0)
+    }
+}
+
+impl ServiceTime {
+    pub fn sample(&self, rng: &mut impl Rng) -> f32 {
+        let seconds = match *self {
+            ServiceTime::Constant(seconds) => seconds,
+            ServiceTime::Uniform { min, max } => min + (max - min) * rng.random::<f32>(),
+            ServiceTime::Normal { mean, std_dev } => {
+                // Box-Muller: 1 - u keeps the logarithm away from zero
+                let (u, v) = (1.0 - rng.random::<f32>(), rng.random::<f32>());
+                mean + std_dev * (-2.0 * u.ln()).sqrt() * (std::f32::consts::TAU * v).cos()
+            }
+            ServiceTime::Exponential { mean } => -mean * (1.0 - rng.random::<f32>()).ln(),
+        };
+        seconds.max(0.0)
+    }
+}
+
+/// The robots lined up for a station, and the spots they wait at.
+#[derive(Component, Debug, Default)]
+pub struct StationQueue {
+    pub spots: Vec<Vec3>,         // front of the line first, no more robots in line than spots
+    pub line: Vec<(Entity, f32)>, // robots in line, front first, with the sim time each joined
+}
+
+impl StationQueue {
+    /// The spot the next robot to join would wait at, if the line isn't full.
+    pub fn next_spot(&self) -> Option<Vec3> {
+        self.spots.get(self.line.len()).copied()
+    }
+
+    /// Puts `robot` at the back of the line, if there is a spot left for it. Returns whether it joined.
+    pub fn join(&mut self, robot: Entity, now: f32) -> bool {
+        if self.next_spot().is_none() {
+            return false;
+        }
+        self.line.push((robot, now));
+        true
+    }
+
+    /// Takes `robot` out of the line. The robots behind it move up a spot.
+    pub fn leave(&mut self, robot: Entity) {
+        self.line.retain(|&(in_line, _)| in_line != robot);
+    }
+
+    /// Drops the robots that are no longer `in_line`, then books the free bays for the robots at the front,
+    /// front first. Returns the robots served, which have left the line.
+    pub fn serve(&mut self, bays: &mut Bays, in_line: impl Fn(Entity) -> bool) -> Vec<Entity> {
+        self.line.retain(|&(robot, _)| in_line(robot));
+        let mut served = Vec::new();
+        while bays.free() > 0 && !self.line.is_empty() {
+            // The front of the line waits at the first spot, so it takes the bay closest to it
+            let (robot, _) = self.line.remove(0);
+            bays.book(robot, self.spots[0]);
+            served.push(robot);
+        }
+        served
+    }
+
+    /// Where each robot in line waits, front first.
+    pub fn positions(&self) -> impl Iterator<Item = (Entity, Vec3)> + '_ {
+        self.line.iter().zip(&self.spots).map(|(&(robot, _), &spot)| (robot, spot))
+    }
+
+    /// Moves the line to new spots. Returns the robots at the back that no longer fit, which have left the line.
+    pub fn set_spots(&mut self, spots: Vec<Vec3>) -> Vec<Entity> {
+        self.spots = spots;
+        let kept = self.line.len().min(self.spots.len());
+        self.line.drain(kept..).map(|(robot, _)| robot).collect()
+    }
+}
 
 #[derive(Component)]
-pub struct ReservedStation(pub Option<Entity>);
+pub struct ReservedStation(pub Option<Entity>); // station being used, heading for or lined up for
 
 #[derive(Component)] 
 pub struct Battery(pub f32); // battery level 0.0 to 100.0
 
+#[derive(Component, Clone, Copy)]
+pub struct BatteryCapacity(pub f32); // watt-hours a new battery holds at 100%
+
+const MIN_HEALTH_SHARE: f32 = 0.01; // a worn-out battery still holds a sliver, so charge and drain rates stay finite
+
+/// Wear of a robot's battery: how much of its nominal capacity is left, and the cycles that wore it down.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct BatteryHealth {
+    pub health: f32,       // state of health: % of the nominal capacity the battery still holds
+    pub cycles: f32,       // equivalent full cycles: the depth of every discharge summed, 1.0 per 100%
+    pub charges: u32,      // discharges ended by a charge
+    pub charged_to: f32,   // charge (%) the battery last left a charger with, the top of the current discharge
+    pub replacements: u32, // batteries swapped for new ones
+}
+
+impl Default for BatteryHealth {
+    fn default() -> Self {
+        Self { health: 100.0, cycles: 0.0, charges: 0, charged_to: 100.0, replacements: 0 }
+    }
+}
+
+impl BatteryHealth {
+    /// Share of the nominal capacity left, from 0 to 1.
+    pub fn capacity_share(&self) -> f32 {
+        (self.health / 100.0).max(MIN_HEALTH_SHARE)
+    }
+
+    /// Ends the current discharge at `charge` (%) and wears the battery for it: a full 100% discharge costs
+    /// `cycle_fade` % of health, a discharge of depth d (0 to 1) costs `cycle_fade * d^dod_exponent`.
+    pub fn end_discharge(&mut self, charge: f32, cycle_fade: f32, dod_exponent: f32) {
+        let depth = ((self.charged_to - charge) / 100.0).clamp(0.0, 1.0);
+        self.cycles += depth;
+        self.charges += 1;
+        self.health = (self.health - cycle_fade * depth.powf(dod_exponent)).max(0.0);
+    }
+
+    /// Swaps the battery for a new one, which comes fully charged.
+    pub fn replace(&mut self) {
+        *self = Self { replacements: self.replacements + 1, ..Self::default() };
+    }
+}
+
+#[derive(Component, Clone, Copy)]
+pub struct ChargerPower(pub f32); // watts each bay of a charger delivers while charging at constant current
+
+#[derive(Component, Default)]
+pub struct WallFollow(pub Option<f32>); // side a robot is following a wall on, see steer_around_walls
+
+#[derive(Component, Clone, Copy)]
+pub struct Footprint(pub f32); // radius of the robot's body, for collision checks
+
+#[derive(Component, Default, Clone, Copy)]
+pub struct Velocity(pub Vec3); // units per second, along the heading, zero while parked
+
+#[derive(Component, Default, Clone, Copy)]
+pub struct Heading(pub f32); // radians counter-clockwise from +x, the way the robot faces
+
+#[derive(Component, Clone, Copy)]
+pub struct Kinematics {
+    pub max_acceleration: f32, // units per second squared, speeding up and braking alike
+    pub max_angular_rate: f32, // radians per second
+}
+
 #[derive(Component)]
 pub struct SavedMemory(pub Option<(RobotState, Vec3, Option<Entity>)>); // stores last action to return to after charging completes
+
+// --- OBSTACLES ---
+/// Solid geometry robots steer around: axis-aligned boxes (racks) and thick segments (walls).
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub enum ObstacleShape {
+    Box { center: Vec2, half_size: Vec2 },
+    Segment { start: Vec2, end: Vec2, half_thickness: f32 },
+}
+
+impl ObstacleShape {
+    /// Distance from `point` to the obstacle's surface (negative inside) and the outward normal there.
+    pub fn distance_and_normal(&self, point: Vec2) -> (f32, Vec2) {
+        match *self {
+            ObstacleShape::Box { center, half_size } => {
+                let offset = point - center;
+                let outside = offset.abs() - half_size;
+                if outside.x > 0.0 || outside.y > 0.0 {
+                    let clamped = offset.clamp(-half_size, half_size);
+                    let away = offset - clamped;
+                    (away.length(), away.normalize_or_zero())
+                } else if outside.x > outside.y {
+                    // Inside: leave through the nearest side
+                    (outside.x, Vec2::new(offset.x.signum(), 0.0))
+                } else {
+                    (outside.y, Vec2::new(0.0, offset.y.signum()))
+                }
+            }
+            ObstacleShape::Segment { start, end, half_thickness } => {
+                let along = end - start;
+                let t = ((point - start).dot(along) / along.length_squared().max(f32::EPSILON)).clamp(0.0, 1.0);
+                let away = point - (start + along * t);
+                let normal = away.try_normalize().unwrap_or_else(|| along.perp().normalize_or_zero());
+                (away.length() - half_thickness, normal)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn robots(count: usize) -> Vec<Entity> {
+        let mut world = World::new();
+        (0..count).map(|_| world.spawn_empty().id()).collect()
+    }
+
+    // A line of `spots` spots, one every 50 units going down from the station
+    fn queue(spots: usize) -> StationQueue {
+        StationQueue { spots: (1..=spots).map(|i| Vec3::new(0.0, -50.0 * i as f32, 0.0)).collect(), line: Vec::new() }
+    }
+
+    fn waiting(queue: &StationQueue) -> Vec<Entity> {
+        queue.line.iter().map(|(robot, _)| *robot).collect()
+    }
+
+    #[test]
+    fn a_robot_that_books_twice_keeps_its_bay() {
+        let robots = robots(2);
+        let mut bays = Bays::new(vec![Vec3::new(-30.0, 0.0, 0.0), Vec3::new(30.0, 0.0, 0.0)]);
+
+        // Closest to the left bay, then asking again from the right: still the left bay, and the right one stays free
+        assert_eq!(bays.book(robots[0], Vec3::new(-100.0, 0.0, 0.0)), Some(bays.docks[0]));
+        assert_eq!(bays.book(robots[0], Vec3::new(100.0, 0.0, 0.0)), Some(bays.docks[0]));
+        assert_eq!(bays.free(), 1);
+        assert_eq!(bays.book(robots[1], Vec3::new(-100.0, 0.0, 0.0)), Some(bays.docks[1]));
+    }
+
+    #[test]
+    fn a_full_station_books_nobody() {
+        let robots = robots(3);
+        let mut bays = Bays::new(vec![Vec3::new(-30.0, 0.0, 0.0), Vec3::new(30.0, 0.0, 0.0)]);
+        bays.book(robots[0], Vec3::ZERO);
+        bays.book(robots[1], Vec3::ZERO);
+
+        assert_eq!(bays.free(), 0);
+        assert_eq!(bays.free_docks().count(), 0);
+        assert_eq!(bays.book(robots[2], Vec3::ZERO), None);
+        assert!(!bays.occupants.contains(&Some(robots[2])));
+    }
+
+    #[test]
+    fn releasing_a_bay_frees_it_for_the_next_robot() {
+        let robots = robots(3);
+        let mut bays = Bays::new(vec![Vec3::new(-30.0, 0.0, 0.0), Vec3::new(30.0, 0.0, 0.0)]);
+        bays.book(robots[0], Vec3::new(-100.0, 0.0, 0.0));
+        bays.book(robots[1], Vec3::new(100.0, 0.0, 0.0));
+
+        bays.release(robots[0]);
+        assert_eq!(bays.occupants, vec![None, Some(robots[1])]);
+        assert_eq!(bays.free_docks().collect::<Vec<_>>(), vec![bays.docks[0]]);
+        assert_eq!(bays.book(robots[2], Vec3::new(100.0, 0.0, 0.0)), Some(bays.docks[0]));
+
+        // Releasing a robot without a bay changes nothing
+        bays.release(robots[0]);
+        assert_eq!(bays.free(), 0);
+    }
+
+    // Wears a battery for one discharge from `from` to `to` (%), as if it last left a charger at `from`
+    fn cycle(health: &mut BatteryHealth, from: f32, to: f32, dod_exponent: f32) {
+        health.charged_to = from;
+        health.end_discharge(to, 1.0, dod_exponent);
+    }
+
+    #[test]
+    fn a_deep_discharge_wears_more_than_shallow_ones_of_the_same_energy() {
+        let (mut deep, mut shallow) = (BatteryHealth::default(), BatteryHealth::default());
+        cycle(&mut deep, 100.0, 0.0, 1.5);
+        for _ in 0..4 {
+            cycle(&mut shallow, 100.0, 75.0, 1.5);
+        }
+
+        // The same energy either way, but a full discharge costs cycle_fade and four of 25% cost 4 * 0.25^1.5 of it
+        assert!((deep.cycles - shallow.cycles).abs() < 1e-5);
+        assert!((deep.health - 99.0).abs() < 1e-4);
+        assert!((shallow.health - 99.5).abs() < 1e-4);
+
+        // An exponent of 1 wears by energy alone
+        let (mut deep, mut shallow) = (BatteryHealth::default(), BatteryHealth::default());
+        cycle(&mut deep, 100.0, 0.0, 1.0);
+        for _ in 0..4 {
+            cycle(&mut shallow, 100.0, 75.0, 1.0);
+        }
+        assert!((deep.health - shallow.health).abs() < 1e-4);
+    }
+
+    #[test]
+    fn discharges_add_up_to_equivalent_full_cycles() {
+        let mut health = BatteryHealth::default();
+        cycle(&mut health, 100.0, 40.0, 1.5);
+        cycle(&mut health, 90.0, 30.0, 1.5);
+        cycle(&mut health, 90.0, 10.0, 1.5);
+        assert_eq!(health.charges, 3);
+        assert!((health.cycles - 2.0).abs() < 1e-5, "0.6 + 0.6 + 0.8 cycles, got {}", health.cycles);
+
+        // Reaching a charger with more than the battery left with, topped up on the way, is no discharge
+        cycle(&mut health, 50.0, 60.0, 1.5);
+        assert_eq!(health.charges, 4);
+        assert!((health.cycles - 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn a_new_battery_starts_over_but_counts_the_replacement() {
+        let mut health = BatteryHealth::default();
+        for _ in 0..10 {
+            cycle(&mut health, 100.0, 0.0, 1.5);
+        }
+        assert!((health.health - 90.0).abs() < 1e-3);
+
+        health.replace();
+        assert_eq!(health.health, 100.0);
+        assert_eq!(health.cycles, 0.0);
+        assert_eq!(health.charges, 0);
+        assert_eq!(health.charged_to, 100.0);
+        assert_eq!(health.replacements, 1);
+        assert_eq!(health.capacity_share(), 1.0);
+
+        health.replace();
+        assert_eq!(health.replacements, 2);
+    }
+
+    #[test]
+    fn a_line_is_served_first_come_first_served() {
+        let robots = robots(4);
+        let mut queue = queue(3);
+        let mut bays = Bays::new(vec![Vec3::ZERO]);
+        for (i, &robot) in robots.iter().enumerate() {
+            assert_eq!(queue.join(robot, i as f32), i < 3, "three spots, so the fourth robot can't join");
+        }
+
+        // One bay: the front of the line gets it, and the others move up
+        assert_eq!(queue.serve(&mut bays, |_| true), vec![robots[0]]);
+        assert_eq!(bays.occupants, vec![Some(robots[0])]);
+        assert_eq!(queue.serve(&mut bays, |_| true), vec![], "nothing left to serve until the bay is free");
+        assert_eq!(queue.positions().collect::<Vec<_>>(), vec![(robots[1], queue.spots[0]), (robots[2], queue.spots[1])]);
+
+        bays.release(robots[0]);
+        assert_eq!(queue.serve(&mut bays, |_| true), vec![robots[1]]);
+        assert_eq!(waiting(&queue), vec![robots[2]]);
+    }
+
+    #[test]
+    fn robots_that_left_are_skipped() {
+        let robots = robots(3);
+        let mut queue = queue(3);
+        let mut bays = Bays::new(vec![Vec3::ZERO]);
+        for &robot in &robots {
+            queue.join(robot, 0.0);
+        }
+
+        // The front robot went charging, so the next one is served
+        assert_eq!(queue.serve(&mut bays, |robot| robot != robots[0]), vec![robots[1]]);
+        assert_eq!(waiting(&queue), vec![robots[2]]);
+    }
+
+    #[test]
+    fn a_spot_left_free_is_taken_again() {
+        let robots = robots(4);
+        let mut queue = queue(3);
+        for &robot in &robots[..3] {
+            queue.join(robot, 0.0);
+        }
+        assert_eq!(queue.next_spot(), None);
+
+        // The middle robot leaves: the one behind it moves up and the last spot opens again
+        queue.leave(robots[1]);
+        assert_eq!(queue.positions().collect::<Vec<_>>(), vec![(robots[0], queue.spots[0]), (robots[2], queue.spots[1])]);
+        assert_eq!(queue.next_spot(), Some(queue.spots[2]));
+
+        assert!(queue.join(robots[3], 1.0));
+        assert_eq!(queue.positions().last(), Some((robots[3], queue.spots[2])));
+        assert_eq!(queue.next_spot(), None);
+    }
+
+    #[test]
+    fn fewer_spots_trim_the_back_of_the_line() {
+        let robots = robots(4);
+        let mut queue = queue(4);
+        for &robot in &robots {
+            queue.join(robot, 0.0);
+        }
+
+        let spots = vec![Vec3::new(50.0, 0.0, 0.0), Vec3::new(100.0, 0.0, 0.0)];
+        assert_eq!(queue.set_spots(spots.clone()), vec![robots[2], robots[3]]);
+        assert_eq!(queue.positions().collect::<Vec<_>>(), vec![(robots[0], spots[0]), (robots[1], spots[1])]);
+
+        // More spots keep everyone in line
+        assert_eq!(queue.set_spots(vec![Vec3::ZERO; 3]), vec![]);
+        assert_eq!(waiting(&queue), robots[..2]);
+    }
+}
@@ -0,0 +1,611 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use bevy::math::Vec2;
+use ron::error::{Position, SpannedError};
+
+use crate::components::{ServiceTime, CHARGER_SIZE, ROBOT_SIZE, STATION_SIZE};
+use crate::navigation::CLEARANCE;
+use crate::resources::{ObstacleDef, OrderDef, OrderSource, SimulationConfig, DEFAULT_CHARGER_POWER};
+
+// --- ERRORS ---
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io { path: PathBuf, source: std::io::Error },
+    Parse { path: PathBuf, source: Box<SpannedError> },
+    Invalid { path: PathBuf, issues: Vec<ConfigIssue> },
+}
+
+/// A single problem found while validating a scenario.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigIssue {
+    pub field: String,
+    pub message: String,
+    pub position: Option<Position>, // where the field appears in the RON source, if found
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, source } => {
+                write!(f, "failed to read scenario '{}': {source}", path.display())
+            }
+            ConfigError::Parse { path, source } => {
+                write!(f, "failed to parse scenario '{}' at {}: {}", path.display(), source.span.start, source.code)
+            }
+            ConfigError::Invalid { path, issues } => {
+                write!(f, "scenario '{}' has {} problem(s):", path.display(), issues.len())?;
+                for issue in issues {
+                    match issue.position {
+                        Some(position) => write!(f, "\n  {}:{position}: {}: {}", path.display(), issue.field, issue.message)?,
+                        None => write!(f, "\n  {}: {}: {}", path.display(), issue.field, issue.message)?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some(source),
+            ConfigError::Parse { source, .. } => Some(source.as_ref()),
+            ConfigError::Invalid { .. } => None,
+        }
+    }
+}
+
+// --- LOADING ---
+
+/// Reads, parses and validates a scenario file, and reads the order CSV it points to, if any.
+pub fn load_config(path: &Path) -> Result<SimulationConfig, ConfigError> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|source| ConfigError::Io { path: path.to_path_buf(), source })?;
+    let mut config = parse_config(path, &source)?;
+
+    if let OrderSource::Csv(csv) = &config.orders {
+        let csv = path.parent().unwrap_or(Path::new("")).join(csv);
+        config.orders = OrderSource::Schedule(load_orders(&csv, &config)?);
+    }
+    Ok(config)
+}
+
+/// Reads an order schedule from CSV: one `time,pickup,dropoff[,priority]` order per line,
+/// optionally under a header line, with blank lines and `#` comments skipped.
+pub fn load_orders(path: &Path, config: &SimulationConfig) -> Result<Vec<OrderDef>, ConfigError> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|source| ConfigError::Io { path: path.to_path_buf(), source })?;
+
+    let mut orders = Vec::new();
+    let mut issues = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || (index == 0 && line.starts_with("time")) {
+            continue;
+        }
+        let position = Some(Position { line: index + 1, col: 1 });
+
+        match parse_order(line) {
+            Ok(order) => {
+                for message in config.order_issues(&order) {
+                    issues.push(ConfigIssue { field: "order".into(), message, position });
+                }
+                orders.push(order);
+            }
+            Err(message) => issues.push(ConfigIssue { field: "order".into(), message, position }),
+        }
+    }
+
+    if issues.is_empty() {
+        Ok(orders)
+    } else {
+        Err(ConfigError::Invalid { path: path.to_path_buf(), issues })
+    }
+}
+
+fn parse_order(line: &str) -> Result<OrderDef, String> {
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+    if !(3..=4).contains(&fields.len()) {
+        return Err(format!("expected time,pickup,dropoff[,priority] (got {} fields)", fields.len()));
+    }
+    let time = fields[0].parse().map_err(|_| format!("time '{}' is not a number", fields[0]))?;
+    let pickup = fields[1].parse().map_err(|_| format!("pickup '{}' is not a station index", fields[1]))?;
+    let dropoff = fields[2].parse().map_err(|_| format!("dropoff '{}' is not a station index", fields[2]))?;
+    let priority = match fields.get(3) {
+        Some(field) => field.parse().map_err(|_| format!("priority '{field}' is not a number from 0 to 255"))?,
+        None => 0,
+    };
+    Ok(OrderDef { time, pickup, dropoff, priority })
+}
+
+pub fn parse_config(path: &Path, source: &str) -> Result<SimulationConfig, ConfigError> {
+    let config: SimulationConfig = ron::from_str(source)
+        .map_err(|source| ConfigError::Parse { path: path.to_path_buf(), source: Box::new(source) })?;
+
+    let issues = config.validate(source);
+    if issues.is_empty() {
+        Ok(config)
+    } else {
+        Err(ConfigError::Invalid { path: path.to_path_buf(), issues })
+    }
+}
+
+// --- VALIDATION ---
+
+impl SimulationConfig {
+    /// Checks the scenario for values that would make the simulation misbehave.
+    /// `source` is the RON text the config was parsed from, used to point at the offending line.
+    pub fn validate(&self, source: &str) -> Vec<ConfigIssue> {
+        let mut issues = Vec::new();
+        let mut report = |field: &str, index: Option<usize>, message: String| {
+            let position = match index {
+                Some(index) => locate_entry(source, field, index),
+                None => locate_field(source, field),
+            };
+            let field = match index {
+                Some(index) => format!("{field}[{index}]"),
+                None => field.to_string(),
+            };
+            issues.push(ConfigIssue { field, message, position });
+        };
+
+        if self.robot_count == 0 {
+            report("robot_count", None, "must be at least 1".into());
+        }
+
+        // Strictly positive values
+        for (field, value) in [
+            ("robot_speed", self.robot_speed),
+            ("max_acceleration", self.max_acceleration),
+            ("max_angular_rate", self.max_angular_rate),
+            ("collision_radius", self.collision_radius),
+            ("state_change_radius", self.state_change_radius),
+            ("capacity_wh", self.battery.capacity_wh),
+            ("dod_exponent", self.battery.dod_exponent),
+            ("stall_window", self.watchdog.stall_window),
+            ("backoff_time", self.watchdog.backoff_time),
+            ("time_scale", self.time_scale),
+        ] {
+            if !value.is_finite() || value <= 0.0 {
+                report(field, None, format!("must be greater than 0 (got {value})"));
+            }
+        }
+
+        // Power draw and wear rates may be zero but never negative
+        for (field, value) in [
+            ("idle_power_w", self.idle_power_w),
+            ("move_power_w", self.move_power_w),
+            ("cycle_fade", self.battery.cycle_fade),
+            ("calendar_fade_per_day", self.battery.calendar_fade_per_day),
+        ] {
+            if !value.is_finite() || value < 0.0 {
+                report(field, None, format!("must not be negative (got {value})"));
+            }
+        }
+
+        // Battery thresholds are percentages
+        for (field, value) in [
+            ("low_battery_threshold", self.low_battery_threshold),
+            ("dead_battery_threshold", self.dead_battery_threshold),
+            ("cv_from", self.battery.cv_from),
+            ("charge_until", self.battery.charge_until),
+            ("replace_below", self.battery.replace_below.unwrap_or(0.0)),
+        ] {
+            if !(0.0..=100.0).contains(&value) {
+                report(field, None, format!("must be between 0 and 100 (got {value})"));
+            }
+        }
+        if self.dead_battery_threshold >= self.low_battery_threshold {
+            report("dead_battery_threshold", None, format!(
+                "must be below low_battery_threshold ({} >= {})",
+                self.dead_battery_threshold, self.low_battery_threshold
+            ));
+        }
+        // Robots leaving the charger below the threshold would head straight back
+        if self.battery.charge_until <= self.low_battery_threshold {
+            report("charge_until", None, format!(
+                "must be above low_battery_threshold ({} <= {})",
+                self.battery.charge_until, self.low_battery_threshold
+            ));
+        }
+
+        // Stations
+        let station_lists = [
+            ("pickup_stations", &self.pickup_stations, STATION_SIZE),
+            ("dropoff_stations", &self.dropoff_stations, STATION_SIZE),
+            ("charger_stations", &self.charger_stations, CHARGER_SIZE),
+        ];
+        let mut placed: Vec<(&str, usize, (f32, f32), f32)> = Vec::new();
+        let mut spots: Vec<(&str, usize, String, Vec2)> = Vec::new(); // bays and queue spots, checked against obstacles below
+
+        for (field, stations, size) in station_lists {
+            if stations.is_empty() {
+                report(field, None, "must contain at least one station".into());
+            }
+
+            for (index, station) in stations.iter().enumerate() {
+                // Queues are for robots waiting on a dropoff or charger; robots never wait for a pickup
+                if !station.queue.is_empty() && field == "pickup_stations" {
+                    report(field, Some(index), "pickup stations can't have a queue".into());
+                }
+                for (spot_index, spot) in station.queue_spots().enumerate() {
+                    if spot.is_finite() {
+                        spots.push((field, index, format!("queue spot {spot_index}"), spot));
+                    } else {
+                        report(field, Some(index), format!("queue spot {spot_index} is not finite"));
+                    }
+                }
+                // Chargers keep a robot until it is charged; only pickups and dropoffs serve one
+                if station.service != ServiceTime::default() && field == "charger_stations" {
+                    report(field, Some(index), "charger stations can't have a service time".into());
+                }
+                if station.power != DEFAULT_CHARGER_POWER && field != "charger_stations" {
+                    report(field, Some(index), "only charger stations have a power".into());
+                }
+                if !station.power.is_finite() || station.power <= 0.0 {
+                    report(field, Some(index), format!("power must be greater than 0 (got {})", station.power));
+                }
+                if let Some(problem) = service_problem(station.service) {
+                    report(field, Some(index), format!("service {problem}"));
+                }
+                // A single bay sits on the station itself, which is checked below like before
+                let docks = station.docks();
+                for (bay, dock) in docks.iter().enumerate().filter(|_| !station.bays.is_empty()) {
+                    if !dock.is_finite() {
+                        report(field, Some(index), format!("bay {bay} is not finite"));
+                        continue;
+                    }
+                    spots.push((field, index, format!("bay {bay}"), *dock));
+                    // Docked robots would stand on each other
+                    if let Some(other) = docks[..bay].iter().position(|other| other.distance(*dock) < ROBOT_SIZE) {
+                        report(field, Some(index), format!("bays {other} and {bay} are closer than a robot ({ROBOT_SIZE})"));
+                    }
+                }
+
+                let (x, y) = station.position;
+                if !x.is_finite() || !y.is_finite() {
+                    report(field, Some(index), format!("has a non-finite position ({x}, {y})"));
+                    continue;
+                }
+
+                // Footprints are axis-aligned squares, so they overlap when both axes do
+                for &(other_field, other_index, (ox, oy), other_size) in &placed {
+                    let reach = (size + other_size) / 2.0;
+                    if (x - ox).abs() < reach && (y - oy).abs() < reach {
+                        report(field, Some(index), format!(
+                            "at ({x}, {y}) overlaps {other_field}[{other_index}] at ({ox}, {oy})"
+                        ));
+                    }
+                }
+                placed.push((field, index, (x, y), size));
+            }
+        }
+
+        // Obstacles
+        for (index, obstacle) in self.obstacles.iter().enumerate() {
+            let points: Vec<(f32, f32)> = match obstacle {
+                ObstacleDef::Rect { min, max } => vec![*min, *max],
+                ObstacleDef::Polyline { points, .. } => points.clone(),
+            };
+            if points.iter().any(|(x, y)| !x.is_finite() || !y.is_finite()) {
+                report("obstacles", Some(index), "has a non-finite point".into());
+                continue;
+            }
+
+            match obstacle {
+                ObstacleDef::Rect { min, max } if min.0 >= max.0 || min.1 >= max.1 => {
+                    report("obstacles", Some(index), format!(
+                        "min ({}, {}) must be below max ({}, {}) on both axes", min.0, min.1, max.0, max.1
+                    ));
+                    continue;
+                }
+                ObstacleDef::Polyline { points, .. } if points.len() < 2 => {
+                    report("obstacles", Some(index), "a polyline needs at least two points".into());
+                    continue;
+                }
+                ObstacleDef::Polyline { thickness, .. } if !thickness.is_finite() || *thickness <= 0.0 => {
+                    report("obstacles", Some(index), format!("thickness must be greater than 0 (got {thickness})"));
+                    continue;
+                }
+                _ => {}
+            }
+
+            // A station inside a rack or wall could never be reached
+            let shapes = obstacle.shapes();
+            for &(field, station_index, (x, y), size) in &placed {
+                let blocked = shapes.iter()
+                    .any(|shape| shape.distance_and_normal(Vec2::new(x, y)).0 < size / 2.0);
+                if blocked {
+                    report(field, Some(station_index), format!("at ({x}, {y}) overlaps obstacles[{index}]"));
+                }
+            }
+            // Path planning keeps robots CLEARANCE away from obstacles, so a spot any closer can't be reached
+            for (field, station_index, spot_name, spot) in &spots {
+                if shapes.iter().any(|shape| shape.distance_and_normal(*spot).0 < CLEARANCE) {
+                    report(field, Some(*station_index), format!(
+                        "{spot_name} at ({}, {}) is too close to obstacles[{index}]", spot.x, spot.y
+                    ));
+                }
+            }
+        }
+
+        // Orders
+        match &self.orders {
+            OrderSource::Poisson { per_hour } if !per_hour.is_finite() || *per_hour <= 0.0 => {
+                report("per_hour", None, format!("must be greater than 0 (got {per_hour})"));
+            }
+            OrderSource::Schedule(orders) => {
+                for (index, order) in orders.iter().enumerate() {
+                    for message in self.order_issues(order) {
+                        report("orders", Some(index), message);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        issues
+    }
+
+    /// Problems with one order of a schedule: a bad time or a station index past the end of its list.
+    pub fn order_issues(&self, order: &OrderDef) -> Vec<String> {
+        let mut issues = Vec::new();
+        if !order.time.is_finite() || order.time < 0.0 {
+            issues.push(format!("time must not be negative (got {})", order.time));
+        }
+        if order.pickup >= self.pickup_stations.len() {
+            issues.push(format!("pickup {} is past the last of {} pickup_stations", order.pickup, self.pickup_stations.len()));
+        }
+        if order.dropoff >= self.dropoff_stations.len() {
+            issues.push(format!("dropoff {} is past the last of {} dropoff_stations", order.dropoff, self.dropoff_stations.len()));
+        }
+        issues
+    }
+}
+
+// What is wrong with a service time distribution, if anything
+fn service_problem(service: ServiceTime) -> Option<String> {
+    let not_negative = |name: &str, value: f32| {
+        (!value.is_finite() || value < 0.0).then(|| format!("{name} must not be negative (got {value})"))
+    };
+    match service {
+        ServiceTime::Constant(seconds) => not_negative("time", seconds),
+        ServiceTime::Uniform { min, max } => not_negative("min", min)
+            .or_else(|| not_negative("max", max))
+            .or_else(|| (min > max).then(|| format!("min must not be above max ({min} > {max})"))),
+        ServiceTime::Normal { mean, std_dev } => not_negative("mean", mean)
+            .or_else(|| not_negative("std_dev", std_dev)),
+        ServiceTime::Exponential { mean } => not_negative("mean", mean),
+    }
+}
+
+// --- SOURCE LOCATIONS ---
+
+/// Iterates over the characters of a RON source that are not inside comments,
+/// yielding each one with its 1-based line/column.
+fn code_chars(source: &str) -> impl Iterator<Item = (usize, char, Position)> + '_ {
+    let mut chars = source.char_indices().peekable();
+    let (mut line, mut col) = (1, 1);
+    let mut in_line_comment = false;
+    let mut in_block_comment = false;
+
+    std::iter::from_fn(move || {
+        loop {
+            let (offset, c) = chars.next()?;
+            let position = Position { line, col };
+            if c == '\n' {
+                line += 1;
+                col = 1;
+                in_line_comment = false;
+            } else {
+                col += 1;
+            }
+
+            if in_line_comment {
+                continue;
+            }
+            if in_block_comment {
+                if c == '*' && chars.peek().map(|&(_, next)| next) == Some('/') {
+                    chars.next();
+                    col += 1;
+                    in_block_comment = false;
+                }
+                continue;
+            }
+            if c == '/' {
+                match chars.peek().map(|&(_, next)| next) {
+                    Some('/') => { in_line_comment = true; continue; }
+                    Some('*') => { in_block_comment = true; continue; }
+                    _ => {}
+                }
+            }
+            return Some((offset, c, position));
+        }
+    })
+}
+
+/// Finds where `field:` is declared in the source.
+fn locate_field(source: &str, field: &str) -> Option<Position> {
+    let code: Vec<(usize, char, Position)> = code_chars(source).collect();
+
+    code.iter().enumerate().find_map(|(i, &(offset, _, position))| {
+        let rest = source.get(offset..)?;
+        let preceded_by_ident = i > 0 && is_ident_char(code[i - 1].1) && code[i - 1].0 + 1 == offset;
+        if preceded_by_ident || !rest.starts_with(field) {
+            return None;
+        }
+        let after = rest[field.len()..].trim_start();
+        after.starts_with(':').then_some(position)
+    })
+}
+
+/// Finds the `index`-th top-level entry of the list declared as `field`.
+fn locate_entry(source: &str, field: &str, index: usize) -> Option<Position> {
+    let start = locate_field(source, field)?;
+    let mut depth = 0usize;
+    let mut seen = 0usize;
+
+    // The list is the first `[` after the field name, which may sit inside a variant like `Schedule([...])`
+    for (_, c, position) in code_chars(source) {
+        if position < start || (depth == 0 && c != '[') {
+            continue;
+        }
+        match c {
+            '[' | '(' => {
+                depth += 1;
+                // Depth 1 is the list itself, depth 2 is one of its entries
+                if depth == 2 {
+                    if seen == index {
+                        return Some(position);
+                    }
+                    seen += 1;
+                }
+            }
+            ']' | ')' => {
+                depth = depth.checked_sub(1)?;
+                if depth == 0 {
+                    return None;
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The shared test scenario as a scenario file, with a second pickup so list entries can be told apart
+    fn scenario(change: impl FnOnce(&mut SimulationConfig)) -> String {
+        let mut config = SimulationConfig { robot_count: 4, ..SimulationConfig::for_tests() };
+        config.pickup_stations.push(config.pickup_stations[0].clone());
+        config.pickup_stations[1].position = (-300.0, 100.0);
+        change(&mut config);
+        ron::ser::to_string_pretty(&config, ron::ser::PrettyConfig::default()).unwrap()
+    }
+
+    fn issues(source: &str) -> Vec<ConfigIssue> {
+        match parse_config(Path::new("test.ron"), source) {
+            Ok(_) => Vec::new(),
+            Err(ConfigError::Invalid { issues, .. }) => issues,
+            Err(err) => panic!("expected a valid or invalid scenario, got {err}"),
+        }
+    }
+
+    // Where `text` first appears in the source
+    fn at(source: &str, text: &str) -> Option<Position> {
+        let offset = source.find(text).unwrap_or_else(|| panic!("'{text}' not in\n{source}"));
+        let line_start = source[..offset].rfind('\n').map_or(0, |newline| newline + 1);
+        Some(Position { line: source[..offset].matches('\n').count() + 1, col: offset - line_start + 1 })
+    }
+
+    #[test]
+    fn accepts_a_valid_scenario() {
+        assert_eq!(issues(&scenario(|_| {})), Vec::new());
+    }
+
+    #[test]
+    fn reports_every_problem_where_it_is() {
+        let source = scenario(|config| {
+            config.robot_speed = -150.0;
+            config.dead_battery_threshold = 50.0;
+        });
+        let found = issues(&source);
+        let fields: Vec<(&str, Option<Position>)> = found.iter().map(|issue| (issue.field.as_str(), issue.position)).collect();
+        assert_eq!(fields, [
+            ("robot_speed", at(&source, "robot_speed:")),
+            ("dead_battery_threshold", at(&source, "dead_battery_threshold:")),
+        ]);
+        assert_eq!(found[0].message, "must be greater than 0 (got -150)");
+    }
+
+    #[test]
+    fn skips_field_names_in_comments() {
+        let source = scenario(|_| {}).replace(
+            "robot_speed: 150.0,",
+            "// robot_speed: was 200.0\n    /* robot_speed: too */ robot_speed: 0.0,",
+        );
+        let found = issues(&source);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].field, "robot_speed");
+        assert_eq!(found[0].position, at(&source, "robot_speed: 0.0"));
+    }
+
+    #[test]
+    fn points_at_the_list_entry() {
+        let source = scenario(|config| config.pickup_stations[1].position = (-290.0, 10.0));
+        let found = issues(&source);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].field, "pickup_stations[1]");
+        assert_eq!(found[0].position, at(&source, "(-290.0, 10.0)"));
+        assert!(found[0].message.contains("overlaps pickup_stations[0]"), "{}", found[0].message);
+    }
+
+    #[test]
+    fn checks_fields_against_each_other() {
+        let source = scenario(|config| config.battery.charge_until = 20.0);
+        let found = issues(&source);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].field, "charge_until");
+        assert_eq!(found[0].position, at(&source, "charge_until:"));
+    }
+
+    #[test]
+    fn reports_a_blank_value_as_a_parse_error() {
+        let source = scenario(|_| {}).replace("robot_count: 4", "robot_count: ");
+        match parse_config(Path::new("test.ron"), &source) {
+            Err(ConfigError::Parse { source: error, .. }) => assert_eq!(Some(error.span.start.line), at(&source, "robot_count:").map(|at| at.line)),
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn rejects_the_old_drain_rates() {
+        // They were % per second, so reading them as watts would quietly change every robot's battery life
+        let source = scenario(|_| {}).replace("idle_power_w", "drain_idle").replace("move_power_w", "drain_move");
+        match parse_config(Path::new("test.ron"), &source) {
+            Err(ConfigError::Parse { source: error, .. }) => assert!(error.to_string().contains("idle_power_w"), "{error}"),
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn points_at_the_scheduled_order() {
+        let source = scenario(|config| config.orders = OrderSource::Schedule(vec![
+            OrderDef { time: 0.0, pickup: 1, dropoff: 0, priority: 0 },
+            OrderDef { time: 5.0, pickup: 0, dropoff: 3, priority: 0 },
+        ]));
+        let found = issues(&source);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].field, "orders[1]");
+        assert_eq!(found[0].position, at(&source, "(\n            time: 5.0"));
+        assert_eq!(found[0].message, "dropoff 3 is past the last of 1 dropoff_stations");
+    }
+
+    #[test]
+    fn parses_order_lines() {
+        assert_eq!(parse_order("12.5, 0, 1"), Ok(OrderDef { time: 12.5, pickup: 0, dropoff: 1, priority: 0 }));
+        assert_eq!(parse_order("3,2,1,5"), Ok(OrderDef { time: 3.0, pickup: 2, dropoff: 1, priority: 5 }));
+
+        for (line, problem) in [
+            ("1,2", "got 2 fields"),
+            ("1,2,3,4,5", "got 5 fields"),
+            ("soon,0,1", "time 'soon'"),
+            ("1,-1,1", "pickup '-1'"),
+            ("1,0,x", "dropoff 'x'"),
+            ("1,0,1,300", "priority '300'"),
+        ] {
+            let err = parse_order(line).unwrap_err();
+            assert!(err.contains(problem), "'{line}': {err}");
+        }
+    }
+}
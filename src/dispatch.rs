@@ -0,0 +1,641 @@
+use bevy::ecs::query::QueryFilter;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+use crate::components::*;
+use crate::events::RobotStateChanged;
+use crate::orders::{AssignedOrder, OrderId, OrderQueue};
+use crate::resources::{DispatchMode, OrderSource, SimulationConfig};
+use crate::systems::spawn_slot;
+use crate::utilityfunctions::entities_in_id_order;
+
+const TOP_UP_BELOW: f32 = 50.0; // battery % under which robots with no orders to work on go charging
+
+/// A way of sharing out free stations (or orders) among the robots waiting for one.
+/// Costs are straight-line distances from each robot to each task.
+pub trait Dispatcher: Send + Sync {
+    /// The task each robot gets, as an index into `tasks`, no task given twice. Robots come in id order,
+    /// tasks in order of preference: station id, or order priority then age.
+    fn assign(&self, robots: &[Vec3], tasks: &[Vec3]) -> Vec<Option<usize>>;
+}
+
+pub fn dispatcher(mode: DispatchMode) -> &'static dyn Dispatcher {
+    match mode {
+        DispatchMode::FirstFree => &FirstFree,
+        DispatchMode::NearestFree => &NearestFree,
+        DispatchMode::Hungarian => &Hungarian,
+        DispatchMode::Auction => &Auction,
+    }
+}
+
+// --- FIRST FREE ---
+
+/// Robots in id order take the tasks in order of preference, wherever they are.
+pub struct FirstFree;
+
+impl Dispatcher for FirstFree {
+    fn assign(&self, robots: &[Vec3], tasks: &[Vec3]) -> Vec<Option<usize>> {
+        (0..robots.len()).map(|robot| (robot < tasks.len()).then_some(robot)).collect()
+    }
+}
+
+// --- NEAREST FREE ---
+
+/// Robots in id order take the closest task still open.
+pub struct NearestFree;
+
+impl Dispatcher for NearestFree {
+    fn assign(&self, robots: &[Vec3], tasks: &[Vec3]) -> Vec<Option<usize>> {
+        let mut open = vec![true; tasks.len()];
+        robots.iter().map(|robot| {
+            let task = closest(*robot, tasks, &open)?;
+            open[task] = false;
+            Some(task)
+        }).collect()
+    }
+}
+
+// --- HUNGARIAN ---
+
+/// The assignment with the least total distance over all waiting robots, solved exactly
+/// with the Hungarian algorithm (Kuhn-Munkres, with potentials) every tick.
+pub struct Hungarian;
+
+impl Dispatcher for Hungarian {
+    fn assign(&self, robots: &[Vec3], tasks: &[Vec3]) -> Vec<Option<usize>> {
+        assign_by_cost(robots, tasks, minimum_assignment)
+    }
+}
+
+// Runs a solver that wants no more rows than columns on the distances, with the smaller side in the rows
+fn assign_by_cost(robots: &[Vec3], tasks: &[Vec3], solve: fn(&[Vec<f32>]) -> Vec<usize>) -> Vec<Option<usize>> {
+    let mut assignment = vec![None; robots.len()];
+    if robots.is_empty() || tasks.is_empty() {
+        return assignment;
+    }
+
+    if robots.len() <= tasks.len() {
+        let cost: Vec<Vec<f32>> = robots.iter().map(|r| tasks.iter().map(|t| r.distance(*t)).collect()).collect();
+        for (robot, task) in solve(&cost).into_iter().enumerate() {
+            assignment[robot] = Some(task);
+        }
+    } else {
+        let cost: Vec<Vec<f32>> = tasks.iter().map(|t| robots.iter().map(|r| r.distance(*t)).collect()).collect();
+        for (task, robot) in solve(&cost).into_iter().enumerate() {
+            assignment[robot] = Some(task);
+        }
+    }
+    assignment
+}
+
+/// Column for each row of `cost` (rows <= columns) so that the summed cost is least.
+fn minimum_assignment(cost: &[Vec<f32>]) -> Vec<usize> {
+    let rows = cost.len();
+    let columns = cost[0].len();
+
+    // 1-based, column 0 standing for "not matched yet"
+    let mut row_potential = vec![0.0f64; rows + 1];
+    let mut column_potential = vec![0.0f64; columns + 1];
+    let mut row_of = vec![0usize; columns + 1];
+    let mut previous = vec![0usize; columns + 1];
+
+    for row in 1..=rows {
+        // Grow a path of tight edges from the new row to an unmatched column
+        row_of[0] = row;
+        let mut column = 0;
+        let mut slack = vec![f64::INFINITY; columns + 1];
+        let mut visited = vec![false; columns + 1];
+        loop {
+            visited[column] = true;
+            let current_row = row_of[column];
+            let mut delta = f64::INFINITY;
+            let mut next = 0;
+            for j in 1..=columns {
+                if visited[j] { continue; }
+                let reduced = cost[current_row - 1][j - 1] as f64 - row_potential[current_row] - column_potential[j];
+                if reduced < slack[j] {
+                    slack[j] = reduced;
+                    previous[j] = column;
+                }
+                if slack[j] < delta {
+                    delta = slack[j];
+                    next = j;
+                }
+            }
+            for j in 0..=columns {
+                if visited[j] {
+                    row_potential[row_of[j]] += delta;
+                    column_potential[j] -= delta;
+                } else {
+                    slack[j] -= delta;
+                }
+            }
+            column = next;
+            if row_of[column] == 0 { break; }
+        }
+
+        // Flip the path
+        while column != 0 {
+            let before = previous[column];
+            row_of[column] = row_of[before];
+            column = before;
+        }
+    }
+
+    let mut assignment = vec![0; rows];
+    for (column, &row) in row_of.iter().enumerate().skip(1) {
+        if row != 0 {
+            assignment[row - 1] = column - 1;
+        }
+    }
+    assignment
+}
+
+// --- AUCTION ---
+
+const AUCTION_EPSILON: f64 = 1.0; // least price raise per bid, in distance units
+
+/// Market-based (Bertsekas' auction): robots without a task bid for the one worth most to them, its distance
+/// plus the price it has reached. A bid raises the price by how much better the task is than the robot's
+/// next best, plus `AUCTION_EPSILON`, and outbids whoever held it, who bids again.
+/// The total distance ends up within `AUCTION_EPSILON` per robot of the least possible.
+/// With more robots than tasks the tasks bid for robots instead, so every round has a winner.
+pub struct Auction;
+
+impl Dispatcher for Auction {
+    fn assign(&self, robots: &[Vec3], tasks: &[Vec3]) -> Vec<Option<usize>> {
+        assign_by_cost(robots, tasks, auction_assignment)
+    }
+}
+
+/// Column for each row of `cost` (rows <= columns), auctioned off row by row.
+fn auction_assignment(cost: &[Vec<f32>]) -> Vec<usize> {
+    let columns = cost[0].len();
+    let mut price = vec![0.0f64; columns];
+    let mut owner: Vec<Option<usize>> = vec![None; columns];
+    let mut assignment = vec![0; cost.len()];
+
+    // Bidders in row order, outbid ones to the back, so every run holds the same auction
+    let mut bidders: VecDeque<usize> = (0..cost.len()).collect();
+    while let Some(row) = bidders.pop_front() {
+        // Best and second best value (the lower the cost and price, the better), the first column on ties
+        let mut best = (f64::NEG_INFINITY, 0);
+        let mut second = f64::NEG_INFINITY;
+        for (column, &c) in cost[row].iter().enumerate() {
+            let value = -(c as f64) - price[column];
+            if value > best.0 {
+                second = best.0;
+                best = (value, column);
+            } else if value > second {
+                second = value;
+            }
+        }
+
+        let (value, column) = best;
+        let margin = if second.is_finite() { value - second } else { 0.0 };
+        price[column] += margin + AUCTION_EPSILON;
+        if let Some(outbid) = owner[column].replace(row) {
+            bidders.push_back(outbid);
+        }
+        assignment[row] = column;
+    }
+    assignment
+}
+
+// Index of the open task closest to `position`, the first one on ties
+fn closest(position: Vec3, tasks: &[Vec3], open: &[bool]) -> Option<usize> {
+    tasks.iter().enumerate()
+        .filter(|(task, _)| open[*task])
+        .min_by(|(_, a), (_, b)| position.distance(**a).total_cmp(&position.distance(**b)))
+        .map(|(task, _)| task)
+}
+
+// Pairs robots with tasks through the dispatcher, as (robot, task) in robot id order
+fn pair<T: Copy>(dispatcher: &dyn Dispatcher, robots: &[(Entity, Vec3)], tasks: &[(T, Vec3)]) -> Vec<(Entity, T)> {
+    let robot_positions: Vec<Vec3> = robots.iter().map(|(_, position)| *position).collect();
+    let task_positions: Vec<Vec3> = tasks.iter().map(|(_, position)| *position).collect();
+    dispatcher.assign(&robot_positions, &task_positions).into_iter().zip(robots)
+        .filter_map(|(task, (robot, _))| Some((*robot, tasks.get(task?)?.0)))
+        .collect()
+}
+
+// --- LINES ---
+
+type StationData = (Entity, &'static StationId, &'static Transform, &'static mut Bays, &'static mut StationQueue);
+
+// One task per free bay, stations in id order: (station, where the bay docks)
+fn free_bays<F: QueryFilter>(stations: &Query<StationData, F>) -> Vec<(Entity, Vec3)> {
+    entities_in_id_order(stations.iter().map(|(e, id, ..)| (e, *id)))
+        .into_iter()
+        .filter_map(|station| stations.get(station).ok())
+        .flat_map(|(station, _, _, bays, _)| bays.free_docks().map(move |dock| (station, dock)).collect::<Vec<_>>())
+        .collect()
+}
+
+// The tasks left once each of `taken` has used up a bay of its station
+fn untaken(tasks: &[(Entity, Vec3)], taken: &[(Entity, Entity)]) -> Vec<(Entity, Vec3)> {
+    let mut untaken = tasks.to_vec();
+    for (_, station) in taken {
+        if let Some(index) = untaken.iter().position(|(task, _)| task == station) {
+            untaken.remove(index);
+        }
+    }
+    untaken
+}
+
+// Serves the line of each station (see `StationQueue::serve`), dropping the robots that left it to charge, die or retire.
+// Returns (robot, station, false) for each robot served: it goes to the station, it doesn't line up
+fn serve_lines<F: QueryFilter>(stations: &mut Query<StationData, F>, in_line: impl Fn(Entity, Entity) -> bool) -> Vec<(Entity, Entity, bool)> {
+    let mut served = Vec::new();
+    for (station, _, _, mut bays, mut queue) in stations.iter_mut() {
+        served.extend(queue.serve(&mut bays, |robot| in_line(robot, station)).into_iter().map(|robot| (robot, station, false)));
+    }
+    served
+}
+
+// Stations with room in their line, in id order, each at the spot the next robot to join would wait at
+fn open_lines<F: QueryFilter>(stations: &Query<StationData, F>) -> Vec<(Entity, Vec3)> {
+    entities_in_id_order(stations.iter().filter(|(.., queue)| queue.next_spot().is_some()).map(|(e, id, ..)| (e, *id)))
+        .into_iter()
+        .filter_map(|station| Some((station, stations.get(station).ok()?.4.next_spot()?)))
+        .collect()
+}
+
+// Free bays no robot was sent to go to robots in other lines: nobody is waiting for them. Returns (robot, station, false)
+// for each robot that leaves its line, like a decision to send it there. `line_of` is the station a robot is lined up for
+fn leave_lines<F: QueryFilter>(
+    dispatcher: &dyn Dispatcher,
+    queued: &[(Entity, Vec3)],
+    untaken: &[(Entity, Vec3)],
+    stations: &mut Query<StationData, F>,
+    line_of: impl Fn(Entity) -> Option<Entity>
+) -> Vec<(Entity, Entity, bool)> {
+    let mut switched = Vec::new();
+    for (robot, station) in pair(dispatcher, queued, untaken) {
+        if let Some(line) = line_of(robot)
+            && let Ok((.., mut queue)) = stations.get_mut(line)
+        {
+            queue.leave(robot);
+        }
+        switched.push((robot, station, false));
+    }
+    switched
+}
+
+// Where each robot in line should be, so lines move up as robots leave the front
+fn line_spots<F: QueryFilter>(stations: &Query<StationData, F>) -> Vec<(Entity, Vec3)> {
+    stations.iter().flat_map(|(.., queue)| queue.positions().collect::<Vec<_>>()).collect()
+}
+
+// --- SYSTEM ---
+
+type DispatchedRobot = (
+    Entity,
+    &'static RobotId,
+    &'static mut RobotState,
+    &'static mut TargetPosition,
+    &'static Transform,
+    &'static mut ReservedStation,
+    &'static Battery,
+    &'static mut SavedMemory,
+    &'static mut AssignedOrder,
+);
+
+/// The state changes dispatch_system makes, each reported as a `RobotStateChanged`.
+#[derive(SystemParam)]
+pub struct Transitions<'w, 's> {
+    time: Res<'w, Time>,
+    changes: MessageWriter<'w, RobotStateChanged>,
+    station_ids: Query<'w, 's, &'static StationId>,
+}
+
+impl Transitions<'_, '_> {
+    fn now(&self) -> f32 {
+        self.time.elapsed_secs()
+    }
+
+    fn change(&mut self, robot: RobotId, state: &mut RobotState, to: RobotState, station: Option<Entity>, battery: f32) {
+        self.changes.write(RobotStateChanged {
+            robot,
+            from: *state,
+            to,
+            station: station.and_then(|entity| self.station_ids.get(entity).ok().copied()),
+            battery,
+            sim_time: self.time.elapsed_secs(),
+        });
+        *state = to;
+    }
+}
+
+/// Everything dispatch_system hands out work with: the robots, the stations of each kind and the orders.
+#[derive(SystemParam)]
+pub struct Dispatch<'w, 's> {
+    config: Res<'w, SimulationConfig>,
+    transitions: Transitions<'w, 's>,
+    orders: ResMut<'w, OrderQueue>,
+    robots: Query<'w, 's, DispatchedRobot, With<Robot>>,
+    pickups: Query<'w, 's, StationData, OnlyPickups>,
+    dropoffs: Query<'w, 's, StationData, OnlyDropoffs>,
+    chargers: Query<'w, 's, StationData, OnlyChargers>,
+}
+
+/// Hands out work to the robots waiting for it, once the state machine is done for the tick:
+/// pickups (or orders) to idle robots, dropoffs to robots carrying a load and chargers to robots running low.
+/// A robot with an order goes to the order's pickup and dropoff; the configured `Dispatcher` decides the rest.
+pub fn dispatch_system(mut dispatch: Dispatch) {
+    // Robots, and the free stations offered to them, in id order so runs are reproducible
+    let robot_order = entities_in_id_order(dispatch.robots.iter().map(|(e, id, ..)| (e, *id)));
+    dispatch.pickups_for_idle(&robot_order);
+    dispatch.dropoffs_for_loaded(&robot_order);
+    dispatch.chargers_for_low(&robot_order);
+}
+
+impl Dispatch<'_, '_> {
+    // 1. Pickups, or orders, for idle robots
+    fn pickups_for_idle(&mut self, robot_order: &[Entity]) {
+        let Self { config, transitions, orders, robots, pickups, chargers, .. } = self;
+        let now = transitions.now();
+        let dispatcher = dispatcher(config.dispatch);
+        let with_orders = config.orders != OrderSource::Unlimited;
+
+        let idle: Vec<(Entity, Vec3)> = robots.iter_many(robot_order)
+            .filter(|(_, _, state, ..)| matches!(**state, RobotState::Idle | RobotState::MovingToPark))
+            .map(|(entity, _, _, _, transform, ..)| (entity, transform.translation))
+            .collect();
+
+        // Orders whose pickup station a reload removed can never be picked up; the robots that held one are idle again
+        orders.cancel_unless(|order| pickups.contains(order.pickup));
+        for &(robot, _) in &idle {
+            if let Ok((.., mut assigned)) = robots.get_mut(robot)
+                && assigned.0.take().is_some()
+            {
+                orders.cancelled += 1;
+            }
+        }
+
+        let mut free_pickups = free_bays(pickups);
+        let tasks: Vec<((Entity, Option<OrderId>), Vec3)> = if with_orders {
+            // Only as many orders as a pickup has free bays are offered, the rest wait for the next idle robot
+            orders.offers(|station| free_pickups.iter().filter(|(free, _)| *free == station).count()).into_iter()
+                .filter_map(|order| {
+                    let index = free_pickups.iter().position(|(free, _)| *free == order.pickup)?;
+                    let (_, dock) = free_pickups.remove(index);
+                    Some(((order.pickup, Some(order.id)), dock))
+                })
+                .collect()
+        } else {
+            free_pickups.iter().map(|&(station, dock)| ((station, None), dock)).collect()
+        };
+
+        let assigned_robots = pair(dispatcher, &idle, &tasks);
+        for &(robot, (station, order)) in &assigned_robots {
+            let Ok((_, id, mut state, mut target, transform, mut reserved, battery, _, mut assigned)) = robots.get_mut(robot) else { continue };
+            let Ok((.., mut bays, _)) = pickups.get_mut(station) else { continue };
+            let Some(dock) = bays.book(robot, transform.translation) else { continue };
+            assigned.0 = order.and_then(|order| orders.take(order)).map(|order| order.assign(now));
+            transitions.change(*id, &mut state, RobotState::MovingToPickup, Some(station), battery.0);
+            target.0 = dock;
+            reserved.0 = Some(station);
+        }
+
+        // Nothing to do: top up while a charger is free, otherwise wait at the spawn slot
+        // rather than on a station someone else needs. Without orders there is always work.
+        let any_free_charger = chargers.iter().any(|(.., bays, _)| bays.free() > 0);
+        for &(robot, position) in &idle {
+            if !with_orders || assigned_robots.iter().any(|(assigned, _)| *assigned == robot) { continue; }
+            let Ok((_, id, mut state, mut target, _, _, battery, ..)) = robots.get_mut(robot) else { continue };
+
+            if battery.0 < TOP_UP_BELOW.min(config.battery.charge_until) && any_free_charger {
+                transitions.change(*id, &mut state, RobotState::WaitingForCharger, None, battery.0);
+            } else if position.distance(spawn_slot(*id)) >= config.state_change_radius {
+                if *state == RobotState::Idle {
+                    transitions.change(*id, &mut state, RobotState::MovingToPark, None, battery.0);
+                    target.0 = spawn_slot(*id);
+                }
+            } else if *state == RobotState::MovingToPark {
+                transitions.change(*id, &mut state, RobotState::Idle, None, battery.0);
+            }
+        }
+    }
+
+    // 2. Dropoffs for loaded robots. The robot at the front of a line gets the station first.
+    // Then an order goes to its own dropoff (unless a reload removed it), first come first served; the other robots may take any
+    fn dropoffs_for_loaded(&mut self, robot_order: &[Entity]) {
+        let Self { config, transitions, robots, pickups, dropoffs, chargers, .. } = self;
+        let now = transitions.now();
+        let dispatcher = dispatcher(config.dispatch);
+
+        let mut decisions: Vec<(Entity, Entity, bool)> = serve_lines(dropoffs, |robot, station| robots.get(robot)
+            .is_ok_and(|(_, _, state, _, _, reserved, ..)| *state == RobotState::QueuedForDropoff && reserved.0 == Some(station)));
+        let loaded: Vec<(Entity, Vec3)> = robots.iter_many(robot_order)
+            .filter(|(_, _, state, ..)| matches!(**state, RobotState::WaitingForDropoff))
+            .map(|(entity, _, _, _, transform, ..)| (entity, transform.translation))
+            .collect();
+        let mut tasks = free_bays(dropoffs);
+        let mut open: Vec<(Entity, Vec3)> = Vec::new();
+
+        for &(robot, position) in &loaded {
+            let Ok((.., assigned)) = robots.get(robot) else { continue };
+            match assigned.0.map(|order| order.dropoff).filter(|&station| dropoffs.contains(station)) {
+                Some(station) => {
+                    if let Some(index) = tasks.iter().position(|&(free, _)| free == station) {
+                        tasks.remove(index);
+                        decisions.push((robot, station, false));
+                    }
+                }
+                None => open.push((robot, position)),
+            }
+        }
+        let taken = pair(dispatcher, &open, &tasks);
+        decisions.extend(taken.iter().map(|&(robot, station)| (robot, station, false)));
+        let queued: Vec<(Entity, Vec3)> = robots.iter_many(robot_order)
+            .filter(|(_, _, state, .., assigned)| **state == RobotState::QueuedForDropoff && assigned.0.is_none())
+            .map(|(entity, _, _, _, transform, ..)| (entity, transform.translation))
+            .collect();
+        decisions.extend(leave_lines(dispatcher, &queued, &untaken(&tasks, &taken), dropoffs, |robot| robots.get(robot).ok().and_then(|(.., reserved, _, _, _)| reserved.0)));
+
+        // Robots still waiting line up where there is room: an order at its own dropoff, the others where the dispatcher sends them
+        let mut open: Vec<(Entity, Vec3)> = Vec::new();
+        for &(robot, position) in &loaded {
+            if decisions.iter().any(|(decided, ..)| *decided == robot) { continue; }
+            let Ok((.., assigned)) = robots.get(robot) else { continue };
+            match assigned.0.map(|order| order.dropoff).filter(|&station| dropoffs.contains(station)) {
+                Some(station) => {
+                    if let Ok((.., mut queue)) = dropoffs.get_mut(station)
+                        && queue.join(robot, now)
+                    {
+                        decisions.push((robot, station, true));
+                    }
+                }
+                None => open.push((robot, position)),
+            }
+        }
+        for (robot, station) in pair(dispatcher, &open, &open_lines(dropoffs)) {
+            if let Ok((.., mut queue)) = dropoffs.get_mut(station)
+                && queue.join(robot, now)
+            {
+                decisions.push((robot, station, true));
+            }
+        }
+        decisions.sort_by_key(|(robot, ..)| robot_order.iter().position(|r| r == robot));
+
+        for (robot, station, lines_up) in decisions {
+            let Ok((_, id, mut state, mut target, transform, mut reserved, battery, ..)) = robots.get_mut(robot) else { continue };
+            let Ok((.., mut bays, _)) = dropoffs.get_mut(station) else { continue };
+            if lines_up {
+                transitions.change(*id, &mut state, RobotState::QueuedForDropoff, Some(station), battery.0);
+            } else {
+                let Some(dock) = bays.book(robot, transform.translation) else { continue };
+                transitions.change(*id, &mut state, RobotState::MovingToDropoff, Some(station), battery.0);
+                target.0 = dock;
+            }
+
+            // Free the pickup or charger bay the robot kept booked while waiting on it
+            if let Some(held) = reserved.0 {
+                if let Ok((.., mut held_bays, _)) = pickups.get_mut(held) { held_bays.release(robot); }
+                if let Ok((.., mut held_bays, _)) = chargers.get_mut(held) { held_bays.release(robot); }
+            }
+            reserved.0 = Some(station);
+        }
+        for (robot, spot) in line_spots(dropoffs) {
+            if let Ok((_, _, _, mut target, ..)) = robots.get_mut(robot) { target.0 = spot; }
+        }
+    }
+
+    // 3. Chargers for robots running low, the front of each line first. Robots left over line up
+    fn chargers_for_low(&mut self, robot_order: &[Entity]) {
+        let Self { config, transitions, robots, pickups, chargers, .. } = self;
+        let now = transitions.now();
+        let dispatcher = dispatcher(config.dispatch);
+
+        let mut decisions = serve_lines(chargers, |robot, station| robots.get(robot)
+            .is_ok_and(|(_, _, state, _, _, reserved, ..)| *state == RobotState::QueuedForCharger && reserved.0 == Some(station)));
+        let low: Vec<(Entity, Vec3)> = robots.iter_many(robot_order)
+            .filter(|(_, _, state, ..)| matches!(**state, RobotState::WaitingForCharger))
+            .map(|(entity, _, _, _, transform, ..)| (entity, transform.translation))
+            .collect();
+        let tasks = free_bays(chargers);
+        let taken = pair(dispatcher, &low, &tasks);
+        decisions.extend(taken.iter().map(|&(robot, station)| (robot, station, false)));
+        let queued: Vec<(Entity, Vec3)> = robots.iter_many(robot_order)
+            .filter(|(_, _, state, ..)| **state == RobotState::QueuedForCharger)
+            .map(|(entity, _, _, _, transform, ..)| (entity, transform.translation))
+            .collect();
+        let switched = leave_lines(dispatcher, &queued, &untaken(&tasks, &taken), chargers, |robot| robots.get(robot).ok().and_then(|(.., reserved, _, _, _)| reserved.0));
+
+        let waiting: Vec<(Entity, Vec3)> = low.into_iter().filter(|(robot, _)| !decisions.iter().any(|(decided, ..)| decided == robot)).collect();
+        decisions.extend(switched);
+        for (robot, station) in pair(dispatcher, &waiting, &open_lines(chargers)) {
+            if let Ok((.., mut queue)) = chargers.get_mut(station)
+                && queue.join(robot, now)
+            {
+                decisions.push((robot, station, true));
+            }
+        }
+        decisions.sort_by_key(|(robot, ..)| robot_order.iter().position(|r| r == robot));
+
+        for (robot, station, lines_up) in decisions {
+            let Ok((_, id, mut state, mut target, transform, mut reserved, battery, mut memory, _)) = robots.get_mut(robot) else { continue };
+            let Ok((.., mut bays, _)) = chargers.get_mut(station) else { continue };
+            if lines_up {
+                transitions.change(*id, &mut state, RobotState::QueuedForCharger, Some(station), battery.0);
+            } else {
+                let Some(dock) = bays.book(robot, transform.translation) else { continue };
+                transitions.change(*id, &mut state, RobotState::MovingToCharger, Some(station), battery.0);
+                target.0 = dock;
+            }
+            // We overwrite reserved.0 with the Charger ID temporarily.
+            // This is fine because we cached the old ID in 'memory'.
+            reserved.0 = Some(station);
+
+            // A robot that was waiting for a dropoff leaves the bay it kept booked, unless it is at the charger it is going to
+            if let Some((RobotState::WaitingForDropoff, saved_target, Some(held))) = memory.0 {
+                if let Ok((.., mut held_bays, _)) = pickups.get_mut(held) { held_bays.release(robot); }
+                if held != station && let Ok((.., mut held_bays, _)) = chargers.get_mut(held) { held_bays.release(robot); }
+                memory.0 = Some((RobotState::WaitingForDropoff, saved_target, None));
+            }
+        }
+        for (robot, spot) in line_spots(chargers) {
+            if let Ok((_, _, _, mut target, ..)) = robots.get_mut(robot) { target.0 = spot; }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    fn positions(rng: &mut ChaCha8Rng, count: usize) -> Vec<Vec3> {
+        (0..count).map(|_| Vec3::new(rng.random_range(-500.0..500.0), rng.random_range(-500.0..500.0), 0.0)).collect()
+    }
+
+    // Least total distance over every way of pairing up as many robots and tasks as possible
+    fn brute_force(robots: &[Vec3], tasks: &[Vec3]) -> f32 {
+        fn search(robots: &[Vec3], tasks: &[Vec3], open: &mut [bool], left: usize) -> f32 {
+            let Some((robot, rest)) = robots.split_first() else { return 0.0 };
+            // A robot may go without a task only while there are more robots left than tasks to fill
+            let mut best = if rest.len() >= left { search(rest, tasks, open, left) } else { f32::INFINITY };
+            for task in 0..tasks.len() {
+                if !open[task] { continue; }
+                open[task] = false;
+                best = best.min(robot.distance(tasks[task]) + search(rest, tasks, open, left - 1));
+                open[task] = true;
+            }
+            best
+        }
+        search(robots, tasks, &mut vec![true; tasks.len()], robots.len().min(tasks.len()))
+    }
+
+    // Total distance of an assignment, checking that no task went to two robots and that no robot that could work went without
+    fn total(robots: &[Vec3], tasks: &[Vec3], assignment: &[Option<usize>]) -> f32 {
+        assert_eq!(assignment.len(), robots.len());
+        let mut taken = vec![false; tasks.len()];
+        for task in assignment.iter().flatten() {
+            assert!(!taken[*task], "task {task} given twice: {assignment:?}");
+            taken[*task] = true;
+        }
+        assert_eq!(assignment.iter().flatten().count(), robots.len().min(tasks.len()), "{assignment:?}");
+        assignment.iter().zip(robots).filter_map(|(task, robot)| Some(robot.distance(tasks[(*task)?]))).sum()
+    }
+
+    const SIZES: [(usize, usize); 6] = [(2, 5), (3, 6), (4, 4), (6, 6), (5, 2), (6, 3)];
+
+    #[test]
+    fn hungarian_finds_the_least_total_distance() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        for (robot_count, task_count) in SIZES {
+            for _ in 0..20 {
+                let (robots, tasks) = (positions(&mut rng, robot_count), positions(&mut rng, task_count));
+                let found = total(&robots, &tasks, &Hungarian.assign(&robots, &tasks));
+                let best = brute_force(&robots, &tasks);
+                assert!((found - best).abs() < 1e-2, "{robot_count} robots, {task_count} tasks: {found} instead of {best}");
+            }
+        }
+    }
+
+    #[test]
+    fn auction_gives_each_task_once_and_comes_close_to_the_least_total() {
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+        for (robot_count, task_count) in SIZES {
+            for _ in 0..20 {
+                let (robots, tasks) = (positions(&mut rng, robot_count), positions(&mut rng, task_count));
+                let found = total(&robots, &tasks, &Auction.assign(&robots, &tasks));
+                let bound = brute_force(&robots, &tasks) + robot_count.min(task_count) as f32 * AUCTION_EPSILON as f32;
+                assert!(found <= bound + 1e-2, "{robot_count} robots, {task_count} tasks: {found} over {bound}");
+            }
+        }
+    }
+
+    #[test]
+    fn auction_settles_robots_after_the_same_task() {
+        // Everyone starts out wanting the task in the middle
+        let robots = [Vec3::new(-10.0, 0.0, 0.0), Vec3::new(10.0, 0.0, 0.0), Vec3::new(0.0, 10.0, 0.0)];
+        let tasks = [Vec3::ZERO, Vec3::new(-100.0, 0.0, 0.0), Vec3::new(100.0, 0.0, 0.0)];
+        let assignment = Auction.assign(&robots, &tasks);
+        total(&robots, &tasks, &assignment);
+        assert_eq!(assignment, vec![Some(1), Some(2), Some(0)]);
+    }
+}
@@ -0,0 +1,179 @@
+use bevy::prelude::*;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::collisions::CollisionDetected;
+use crate::components::*;
+use crate::orders::OrderCompleted;
+use crate::watchdog::StallDetected;
+
+/// Sent whenever a robot changes state, by the state machine, the battery system or a config reload.
+#[derive(Message, Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct RobotStateChanged {
+    pub robot: RobotId,
+    pub from: RobotState,
+    pub to: RobotState,
+    pub station: Option<StationId>, // the station being claimed, worked at or released, if any
+    pub battery: f32,
+    pub sim_time: f32,
+}
+
+/// Writes every `RobotStateChanged` as one JSON line to `events.jsonl` in the output directory,
+/// every `StallDetected` to `stalls.jsonl`, every `CollisionDetected` to `collisions.jsonl`
+/// and every `OrderCompleted` to `orders.jsonl`.
+/// The files are opened in append mode, so earlier runs into the same directory are kept.
+#[derive(Resource)]
+pub struct EventLog {
+    writer: BufWriter<File>,
+    stalls: BufWriter<File>,
+    collisions: BufWriter<File>,
+    orders: BufWriter<File>,
+    failed: bool,
+}
+
+impl EventLog {
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let open = |name: &str| OpenOptions::new().create(true).append(true).open(dir.join(name)).map(BufWriter::new);
+        Ok(Self {
+            writer: open("events.jsonl")?,
+            stalls: open("stalls.jsonl")?,
+            collisions: open("collisions.jsonl")?,
+            orders: open("orders.jsonl")?,
+            failed: false,
+        })
+    }
+
+    fn write(writer: &mut BufWriter<File>, event: &impl Serialize) -> io::Result<()> {
+        serde_json::to_writer(&mut *writer, event)?;
+        writeln!(writer)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.stalls.flush()?;
+        self.collisions.flush()?;
+        self.orders.flush()
+    }
+
+    // Reports the first write error and stops logging, rather than failing every tick
+    fn check(&mut self, result: io::Result<()>) {
+        if let Err(err) = result {
+            eprintln!("error: event log stopped: {err}");
+            self.failed = true;
+        }
+    }
+}
+
+/// Adds the log sink. Does nothing unless an `EventLog` resource is inserted.
+pub struct EventLogPlugin;
+
+impl Plugin for EventLogPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(FixedUpdate, write_event_log
+                .after(crate::SwarmSimSet::Metrics)
+                .run_if(resource_exists::<EventLog>))
+            .add_systems(Last, flush_event_log
+                .run_if(resource_exists::<EventLog>.and(on_message::<AppExit>)));
+    }
+}
+
+// --- SYSTEMS ---
+
+pub fn write_event_log(
+    mut log: ResMut<EventLog>,
+    mut transitions: MessageReader<RobotStateChanged>,
+    mut stalls: MessageReader<StallDetected>,
+    mut collisions: MessageReader<CollisionDetected>,
+    mut orders: MessageReader<OrderCompleted>
+)
+{
+    if log.failed {
+        transitions.clear();
+        stalls.clear();
+        collisions.clear();
+        orders.clear();
+        return;
+    }
+    let log = &mut *log;
+    let result = transitions.read().try_for_each(|event| EventLog::write(&mut log.writer, event))
+        .and_then(|()| stalls.read().try_for_each(|event| EventLog::write(&mut log.stalls, event)))
+        .and_then(|()| collisions.read().try_for_each(|event| EventLog::write(&mut log.collisions, event)))
+        .and_then(|()| orders.read().try_for_each(|event| EventLog::write(&mut log.orders, event)));
+    log.check(result);
+}
+
+pub fn flush_event_log(mut log: ResMut<EventLog>) {
+    let result = log.flush();
+    log.check(result);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orders::OrderId;
+
+    fn logged(dir: &Path) -> App {
+        let mut app = App::new();
+        app.insert_resource(EventLog::open(dir).unwrap())
+            .add_message::<RobotStateChanged>()
+            .add_message::<StallDetected>()
+            .add_message::<CollisionDetected>()
+            .add_message::<OrderCompleted>()
+            .add_systems(Update, write_event_log);
+        app
+    }
+
+    fn change(sim_time: f32) -> RobotStateChanged {
+        RobotStateChanged {
+            robot: RobotId(3),
+            from: RobotState::MovingToCharger,
+            to: RobotState::Charging,
+            station: Some(StationId(2)),
+            battery: 24.5,
+            sim_time,
+        }
+    }
+
+    #[test]
+    fn writes_each_message_as_a_json_line_to_its_file() {
+        let dir = std::env::temp_dir().join(format!("swarm_events_lines_{}", std::process::id()));
+        let mut app = logged(&dir);
+        app.world_mut().write_message(change(12.5));
+        app.world_mut().write_message(OrderCompleted {
+            order: OrderId(4), robot: RobotId(3), pickup: Some(StationId(0)), dropoff: None,
+            priority: 1, created: 2.0, assigned: 3.5, sim_time: 12.5,
+        });
+        app.update();
+        app.world_mut().resource_mut::<EventLog>().flush().unwrap();
+
+        let read = |file: &str| std::fs::read_to_string(dir.join(file)).unwrap();
+        assert_eq!(read("events.jsonl"),
+            "{\"robot\":3,\"from\":\"MovingToCharger\",\"to\":\"Charging\",\"station\":2,\"battery\":24.5,\"sim_time\":12.5}\n");
+        assert_eq!(read("orders.jsonl"),
+            "{\"order\":4,\"robot\":3,\"pickup\":0,\"dropoff\":null,\"priority\":1,\"created\":2.0,\"assigned\":3.5,\"sim_time\":12.5}\n");
+        assert_eq!(read("stalls.jsonl"), "");
+        assert_eq!(read("collisions.jsonl"), "");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_second_run_appends_to_the_first() {
+        let dir = std::env::temp_dir().join(format!("swarm_events_append_{}", std::process::id()));
+        for sim_time in [1.0, 2.0] {
+            let mut app = logged(&dir);
+            app.world_mut().write_message(change(sim_time));
+            app.update();
+            app.world_mut().resource_mut::<EventLog>().flush().unwrap();
+        }
+
+        let events = std::fs::read_to_string(dir.join("events.jsonl")).unwrap();
+        let times: Vec<f32> = events.lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["sim_time"].as_f64().unwrap() as f32)
+            .collect();
+        assert_eq!(times, [1.0, 2.0]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,238 @@
+use bevy::prelude::*;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::components::*;
+use crate::metrics::Metrics;
+
+/// Samples robot state and fleet KPIs at a fixed simulated interval and writes them to
+/// `robots.csv`, `robots.jsonl`, `kpis.csv` and `kpis.jsonl` in the output directory.
+#[derive(Resource)]
+pub struct Exporter {
+    pub sample_interval: f32, // simulated seconds between samples
+    next_sample: f32,
+    robots_csv: BufWriter<File>,
+    robots_jsonl: BufWriter<File>,
+    kpis_csv: BufWriter<File>,
+    kpis_jsonl: BufWriter<File>,
+    failed: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RobotSample {
+    pub time: f32,
+    pub robot: u32,
+    pub x: f32,
+    pub y: f32,
+    pub heading: f32, // radians counter-clockwise from +x
+    pub state: RobotState,
+    pub battery: f32,
+    pub battery_health: f32,
+    pub battery_cycles: f32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct KpiSample {
+    pub time: f32,
+    pub deliveries: u64,
+    pub deliveries_per_hour: f32,
+    pub cycle_time_mean: f32,
+    pub fleet_utilization: f32,
+    pub charger_occupancy: f32,
+    pub distance_travelled: f32,
+    pub deaths: u64,
+    pub robots_alive: usize,
+    pub battery_health_mean: f32,
+    pub battery_replacements: u64,
+}
+
+const ROBOTS_CSV_HEADER: &str = "time,robot,x,y,heading,state,battery,battery_health,battery_cycles";
+const KPIS_CSV_HEADER: &str = "time,deliveries,deliveries_per_hour,cycle_time_mean,fleet_utilization,charger_occupancy,distance_travelled,deaths,robots_alive,battery_health_mean,battery_replacements";
+
+impl Exporter {
+    /// Creates (or truncates) the export files in `dir`.
+    pub fn create(dir: &Path, sample_interval: f32) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let open = |name: &str| File::create(dir.join(name)).map(BufWriter::new);
+
+        let mut exporter = Self {
+            sample_interval,
+            next_sample: 0.0,
+            robots_csv: open("robots.csv")?,
+            robots_jsonl: open("robots.jsonl")?,
+            kpis_csv: open("kpis.csv")?,
+            kpis_jsonl: open("kpis.jsonl")?,
+            failed: false,
+        };
+        writeln!(exporter.robots_csv, "{ROBOTS_CSV_HEADER}")?;
+        writeln!(exporter.kpis_csv, "{KPIS_CSV_HEADER}")?;
+        Ok(exporter)
+    }
+
+    fn write_robot(&mut self, sample: &RobotSample) -> io::Result<()> {
+        writeln!(self.robots_csv, "{},{},{},{},{},{:?},{},{},{}",
+            sample.time, sample.robot, sample.x, sample.y, sample.heading, sample.state, sample.battery,
+            sample.battery_health, sample.battery_cycles)?;
+        serde_json::to_writer(&mut self.robots_jsonl, sample)?;
+        writeln!(self.robots_jsonl)
+    }
+
+    fn write_kpis(&mut self, sample: &KpiSample) -> io::Result<()> {
+        writeln!(self.kpis_csv, "{},{},{},{},{},{},{},{},{},{},{}",
+            sample.time, sample.deliveries, sample.deliveries_per_hour, sample.cycle_time_mean,
+            sample.fleet_utilization, sample.charger_occupancy, sample.distance_travelled,
+            sample.deaths, sample.robots_alive, sample.battery_health_mean, sample.battery_replacements)?;
+        serde_json::to_writer(&mut self.kpis_jsonl, sample)?;
+        writeln!(self.kpis_jsonl)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.robots_csv.flush()?;
+        self.robots_jsonl.flush()?;
+        self.kpis_csv.flush()?;
+        self.kpis_jsonl.flush()
+    }
+
+    // Reports the first write error and stops exporting, rather than failing every tick
+    fn check(&mut self, result: io::Result<()>) {
+        if let Err(err) = result {
+            eprintln!("error: export stopped: {err}");
+            self.failed = true;
+        }
+    }
+}
+
+/// Adds the sampling systems. Does nothing unless an `Exporter` resource is inserted.
+pub struct ExportPlugin;
+
+impl Plugin for ExportPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(FixedUpdate, export_samples
+                .after(crate::SwarmSimSet::Metrics)
+                .run_if(resource_exists::<Exporter>))
+            .add_systems(Last, flush_exports
+                .run_if(resource_exists::<Exporter>.and(on_message::<AppExit>)));
+    }
+}
+
+// --- SYSTEMS ---
+
+type SampledRobot = (&'static RobotId, &'static Transform, &'static Heading, &'static RobotState, &'static Battery, &'static BatteryHealth);
+
+pub fn export_samples(
+    time: Res<Time>,
+    metrics: Res<Metrics>,
+    mut exporter: ResMut<Exporter>,
+    robot_query: Query<SampledRobot, With<Robot>>
+)
+{
+    let now = time.elapsed_secs();
+    if exporter.failed || now < exporter.next_sample { return; }
+    exporter.next_sample += exporter.sample_interval;
+
+    // Robots in id order so files from two deterministic runs compare equal
+    let mut robots: Vec<RobotSample> = robot_query.iter().map(|(id, transform, heading, state, battery, health)| RobotSample {
+        time: now,
+        robot: id.0,
+        x: transform.translation.x,
+        y: transform.translation.y,
+        heading: heading.0,
+        state: *state,
+        battery: battery.0,
+        battery_health: health.health,
+        battery_cycles: health.cycles,
+    }).collect();
+    robots.sort_unstable_by_key(|sample| sample.robot);
+
+    // Only the KPIs sampled: the full report sorts every cycle time so far
+    let kpis = KpiSample {
+        time: now,
+        deliveries: metrics.deliveries_completed,
+        deliveries_per_hour: metrics.deliveries_per_hour(),
+        cycle_time_mean: metrics.cycle_time_mean(),
+        fleet_utilization: metrics.fleet_utilization(),
+        charger_occupancy: metrics.charger_occupancy(),
+        distance_travelled: metrics.distance_travelled(),
+        deaths: metrics.deaths,
+        robots_alive: robots.iter().filter(|sample| sample.state != RobotState::Dead).count(),
+        battery_health_mean: metrics.battery_health_mean(),
+        battery_replacements: metrics.battery_replacements(),
+    };
+
+    let result = robots.iter()
+        .try_for_each(|sample| exporter.write_robot(sample))
+        .and_then(|_| exporter.write_kpis(&kpis));
+    exporter.check(result);
+}
+
+pub fn flush_exports(mut exporter: ResMut<Exporter>) {
+    let result = exporter.flush();
+    exporter.check(result);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // Samples two robots every second for two seconds and returns the four files' contents
+    fn export(name: &str) -> [String; 4] {
+        let dir = std::env::temp_dir().join(format!("swarm_export_{name}_{}", std::process::id()));
+        let mut app = App::new();
+        app.insert_resource(Time::<()>::default())
+            .insert_resource(Metrics { deliveries_completed: 3, deaths: 1, ..default() })
+            .insert_resource(Exporter::create(&dir, 1.0).unwrap())
+            .add_systems(Update, export_samples);
+        // Spawned out of id order
+        for (id, x, state, battery) in [(1, 20.5, RobotState::Dead, 0.0), (0, -10.0, RobotState::MovingToPickup, 87.5)] {
+            app.world_mut().spawn((Robot, RobotId(id), Transform::from_xyz(x, 5.0, 0.0), Heading(1.5), state, Battery(battery), BatteryHealth::default()));
+        }
+        for _ in 0..4 {
+            app.update();
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs_f32(0.5));
+        }
+        app.world_mut().resource_mut::<Exporter>().flush().unwrap();
+
+        let files = ["robots.csv", "robots.jsonl", "kpis.csv", "kpis.jsonl"]
+            .map(|file| std::fs::read_to_string(dir.join(file)).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+        files
+    }
+
+    #[test]
+    fn writes_one_line_per_robot_and_one_kpi_line_per_sample() {
+        let [robots_csv, robots_jsonl, kpis_csv, kpis_jsonl] = export("lines");
+
+        assert_eq!(robots_csv.lines().collect::<Vec<_>>(), [
+            ROBOTS_CSV_HEADER,
+            "0,0,-10,5,1.5,MovingToPickup,87.5,100,0",
+            "0,1,20.5,5,1.5,Dead,0,100,0",
+            "1,0,-10,5,1.5,MovingToPickup,87.5,100,0",
+            "1,1,20.5,5,1.5,Dead,0,100,0",
+        ]);
+        assert_eq!(robots_jsonl.lines().next(), Some(
+            r#"{"time":0.0,"robot":0,"x":-10.0,"y":5.0,"heading":1.5,"state":"MovingToPickup","battery":87.5,"battery_health":100.0,"battery_cycles":0.0}"#
+        ));
+        assert_eq!(robots_jsonl.lines().count(), 4);
+
+        assert_eq!(kpis_csv.lines().collect::<Vec<_>>(), [
+            KPIS_CSV_HEADER,
+            "0,3,0,0,0,0,0,1,1,0,0",
+            "1,3,0,0,0,0,0,1,1,0,0",
+        ]);
+        assert_eq!(kpis_jsonl.lines().last(), Some(
+            r#"{"time":1.0,"deliveries":3,"deliveries_per_hour":0.0,"cycle_time_mean":0.0,"fleet_utilization":0.0,"charger_occupancy":0.0,"distance_travelled":0.0,"deaths":1,"robots_alive":1,"battery_health_mean":0.0,"battery_replacements":0}"#
+        ));
+    }
+
+    #[test]
+    fn every_csv_line_has_a_value_per_header_column() {
+        let [robots_csv, _, kpis_csv, _] = export("columns");
+        for csv in [robots_csv, kpis_csv] {
+            let columns = csv.lines().next().unwrap().split(',').count();
+            assert!(csv.lines().all(|line| line.split(',').count() == columns), "{csv}");
+        }
+    }
+}
@@ -0,0 +1,368 @@
+use bevy::prelude::*;
+use std::cmp::Reverse;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use crate::cli::CliArgs;
+use crate::components::*;
+use crate::config::load_config;
+use crate::events::RobotStateChanged;
+use crate::orders::{AssignedOrder, OrderQueue};
+use crate::resources::{SimulationConfig, StationDef};
+use crate::systems::{spawn_obstacles, spawn_robot, spawn_station};
+
+/// Reloads the scenario file from disk whenever it changes.
+pub struct HotReloadPlugin {
+    pub path: PathBuf,
+    pub overrides: CliArgs,
+}
+
+impl Plugin for HotReloadPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ConfigWatcher::new(self.path.clone(), self.overrides.clone()))
+            .add_systems(Update, (
+                watch_config,
+                apply_config_changes.run_if(
+                    resource_changed::<SimulationConfig>.and(not(resource_added::<SimulationConfig>))
+                ),
+            ).chain());
+    }
+}
+
+// How often the scenario file's modification time is checked (real seconds)
+const POLL_INTERVAL: f32 = 0.5;
+
+/// Watches the scenario file and swaps in the new config when it changes on disk.
+#[derive(Resource)]
+pub struct ConfigWatcher {
+    pub path: PathBuf,
+    pub overrides: CliArgs, // re-applied on every reload so the command line keeps priority
+    last_modified: Option<SystemTime>,
+    poll: Timer,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: PathBuf, overrides: CliArgs) -> Self {
+        Self {
+            last_modified: modified_time(&path),
+            path,
+            overrides,
+            poll: Timer::from_seconds(POLL_INTERVAL, TimerMode::Repeating),
+        }
+    }
+}
+
+fn modified_time(path: &PathBuf) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// --- WATCHER ---
+
+pub fn watch_config(
+    time: Res<Time<Real>>,
+    mut watcher: ResMut<ConfigWatcher>,
+    mut config: ResMut<SimulationConfig>
+) 
+{
+    watcher.poll.tick(time.delta());
+    if !watcher.poll.just_finished() { return; }
+
+    let modified = modified_time(&watcher.path);
+    if modified.is_none() || modified == watcher.last_modified { return; }
+    watcher.last_modified = modified;
+
+    // A broken edit keeps the previous config running
+    match load_config(&watcher.path) {
+        Ok(mut new_config) => {
+            watcher.overrides.apply_overrides(&mut new_config);
+            println!("🔄 Reloaded '{}'", watcher.path.display());
+            *config = new_config;
+        }
+        Err(err) => eprintln!("Ignoring config change: {err}"),
+    }
+}
+
+// --- APPLY ---
+
+type RobotData = (
+    Entity,
+    &'static RobotId,
+    &'static mut Speed,
+    &'static mut BatteryCapacity,
+    &'static mut RobotState,
+    &'static mut ReservedStation,
+    &'static mut SavedMemory,
+    &'static Battery,
+    &'static mut Kinematics,
+    &'static AssignedOrder,
+);
+
+type StationData = (
+    Entity,
+    &'static StationId,
+    &'static Transform,
+    &'static mut Bays,
+    &'static mut StationQueue,
+    &'static mut ServiceTime,
+    Option<&'static mut ChargerPower>,
+    Has<PickupStation>,
+    Has<DropoffStation>,
+    Has<ChargerStation>,
+);
+
+/// Brings the world in line with a freshly reloaded config.
+/// Collision radius, thresholds and power draw are read from the config every tick, so they need no work here.
+#[allow(clippy::too_many_arguments)]
+pub fn apply_config_changes(
+    mut commands: Commands,
+    config: Res<SimulationConfig>,
+    time: Res<Time<Fixed>>,
+    mut transitions: MessageWriter<RobotStateChanged>,
+    mut orders: ResMut<OrderQueue>,
+    mut robot_query: Query<RobotData, With<Robot>>,
+    mut station_query: Query<StationData>,
+    obstacle_query: Query<Entity, With<Obstacle>>
+) 
+{
+    // 1. Per-robot parameters
+    for (_, _, mut speed, mut capacity, _, _, _, _, mut kinematics, _) in &mut robot_query {
+        speed.0 = config.robot_speed;
+        kinematics.max_acceleration = config.max_acceleration;
+        kinematics.max_angular_rate = config.max_angular_rate;
+        capacity.0 = config.battery.capacity_wh;
+    }
+
+    // 2. Obstacles hold no state, so they are simply rebuilt
+    for obstacle in &obstacle_query {
+        commands.entity(obstacle).despawn();
+    }
+    spawn_obstacles(&mut commands, &config);
+
+    // 3. Stations: keep the ones whose position is still listed, spawn the new ones, collect the rest.
+    // Kept stations take the new queue spots, service time and charger power; robots in line past the last spot leave the line,
+    // robots already at work finish with the time they drew.
+    // Bays that moved or went away start empty, and the robots that had them let go
+    let mut removed: Vec<Entity> = Vec::new();
+    let mut rebayed: Vec<Entity> = Vec::new();
+    let mut unqueued: Vec<Entity> = Vec::new();
+    let mut next_station_id = station_query.iter().map(|(_, id, ..)| id.0 + 1).max().unwrap_or(0);
+    for kind in [StationKind::Pickup, StationKind::Dropoff, StationKind::Charger] {
+        let mut wanted: Vec<StationDef> = config.stations(kind).to_vec();
+
+        for (entity, _, transform, mut bays, mut queue, mut service, power, is_pickup, is_dropoff, is_charger) in &mut station_query {
+            let matches_kind = match kind {
+                StationKind::Pickup => is_pickup,
+                StationKind::Dropoff => is_dropoff,
+                StationKind::Charger => is_charger,
+            };
+            if !matches_kind { continue; }
+
+            let position = (transform.translation.x, transform.translation.y);
+            match wanted.iter().position(|station| station.position == position) {
+                Some(i) => {
+                    let station = wanted.swap_remove(i);
+                    let docks: Vec<Vec3> = station.docks().into_iter().map(|dock| dock.extend(0.0)).collect();
+                    if docks != bays.docks {
+                        *bays = Bays::new(docks);
+                        rebayed.push(entity);
+                    }
+                    unqueued.extend(queue.set_spots(station.queue_spots().map(|spot| spot.extend(0.0)).collect()));
+                    *service = station.service;
+                    if let Some(mut power) = power {
+                        power.0 = station.power;
+                    }
+                }
+                None => removed.push(entity),
+            }
+        }
+
+        for station in wanted {
+            spawn_station(&mut commands, kind, StationId(next_station_id), &station);
+            next_station_id += 1;
+        }
+    }
+
+    // 4. Robots holding a removed station or a bay that changed, or out of line, drop it and go back to asking for one
+    for (robot, robot_id, _, _, mut state, mut reserved, mut memory, battery, ..) in &mut robot_query {
+        if let Some(station) = reserved.0
+            && (removed.contains(&station) || (rebayed.contains(&station) && !state.is_queued()) || unqueued.contains(&robot))
+        {
+            let to = state.without_station();
+            transitions.write(RobotStateChanged {
+                robot: *robot_id,
+                from: *state,
+                to,
+                station: station_query.get(station).ok().map(|(_, id, ..)| *id),
+                battery: battery.0,
+                sim_time: time.elapsed_secs(),
+            });
+            reserved.0 = None;
+            *state = to;
+        }
+        if let Some((saved_state, saved_target, Some(key))) = memory.0
+            && (removed.contains(&key) || rebayed.contains(&key))
+        {
+            memory.0 = Some((saved_state.without_station(), saved_target, None));
+        }
+    }
+    for station in &removed {
+        commands.entity(*station).despawn();
+    }
+
+    // 5. Fleet size
+    let fleet: Vec<(Entity, RobotId, RobotState)> = robot_query.iter().map(|(entity, id, _, _, state, ..)| (entity, *id, *state)).collect();
+
+    if fleet.len() < config.robot_count {
+        let first_id = fleet.iter().map(|(_, id, _)| id.0 + 1).max().unwrap_or(0);
+        for offset in 0..(config.robot_count - fleet.len()) {
+            spawn_robot(&mut commands, &config, RobotId(first_id + offset as u32));
+        }
+    } else if fleet.len() > config.robot_count {
+        // Retire dead robots first, then idle ones, then the newest
+        let mut candidates = fleet;
+        candidates.sort_by_key(|(_, id, state)| (retire_priority(*state), Reverse(*id)));
+        let excess = candidates.len() - config.robot_count;
+
+        for (robot, ..) in candidates.into_iter().take(excess) {
+            // Unlock any bay the robot was holding, including the one it saved before charging.
+            // A robot in line holds no bay yet; the line drops it by itself.
+            // Its order goes back in line for another robot to start over
+            if let Ok((_, _, _, _, _, reserved, memory, _, _, assigned)) = robot_query.get(robot) {
+                let saved_key = memory.0.and_then(|(_, _, key)| key);
+                for station in [reserved.0, saved_key].into_iter().flatten() {
+                    if let Ok((_, _, _, mut bays, ..)) = station_query.get_mut(station) {
+                        bays.release(robot);
+                    }
+                }
+                if let Some(order) = assigned.0 {
+                    orders.requeue(order);
+                }
+            }
+            commands.entity(robot).despawn();
+        }
+    }
+}
+
+fn retire_priority(state: RobotState) -> u8 {
+    match state {
+        RobotState::Dead => 0,
+        RobotState::Idle | RobotState::MovingToPark => 1,
+        _ => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orders::{Order, OrderId};
+    use crate::systems::spawn_stations;
+    use bevy::ecs::message::Messages;
+
+    // The test scenario's stations and robots, with the reload system waiting for a config change
+    fn warehouse() -> App {
+        let config = SimulationConfig::for_tests();
+        let mut app = App::new();
+        app.insert_resource(Time::<Fixed>::default())
+            .init_resource::<OrderQueue>()
+            .add_message::<RobotStateChanged>()
+            .add_systems(Update, apply_config_changes);
+
+        let mut commands = app.world_mut().commands();
+        spawn_stations(&mut commands, &config);
+        for id in 0..config.robot_count as u32 {
+            spawn_robot(&mut commands, &config, RobotId(id));
+        }
+        app.world_mut().flush();
+        app.insert_resource(config);
+        app
+    }
+
+    fn reload(app: &mut App, change: impl FnOnce(&mut SimulationConfig)) {
+        change(&mut app.world_mut().resource_mut::<SimulationConfig>());
+        app.update();
+    }
+
+    fn station<Kind: Component>(app: &mut App) -> Entity {
+        app.world_mut().query_filtered::<Entity, With<Kind>>().single(app.world()).unwrap()
+    }
+
+    fn robot(app: &mut App, id: u32) -> Entity {
+        app.world_mut().query::<(Entity, &RobotId)>().iter(app.world())
+            .find_map(|(entity, robot)| (robot.0 == id).then_some(entity))
+            .unwrap()
+    }
+
+    // Sends robot `id` to the station in `state`, booked into its bay
+    fn send(app: &mut App, id: u32, station: Entity, state: RobotState) -> Entity {
+        let robot = robot(app, id);
+        app.world_mut().get_mut::<Bays>(station).unwrap().book(robot, Vec3::ZERO);
+        *app.world_mut().get_mut::<RobotState>(robot).unwrap() = state;
+        app.world_mut().get_mut::<ReservedStation>(robot).unwrap().0 = Some(station);
+        robot
+    }
+
+    fn transitions(app: &mut App) -> Vec<(RobotId, RobotState, RobotState)> {
+        app.world_mut().resource_mut::<Messages<RobotStateChanged>>().drain()
+            .map(|change| (change.robot, change.from, change.to))
+            .collect()
+    }
+
+    #[test]
+    fn a_robot_loses_a_removed_station_and_asks_again() {
+        let mut app = warehouse();
+        let pickup = station::<PickupStation>(&mut app);
+        let robot = send(&mut app, 0, pickup, RobotState::MovingToPickup);
+
+        reload(&mut app, |config| config.pickup_stations[0].position = (-300.0, 200.0));
+
+        assert!(app.world().get_entity(pickup).is_err());
+        let moved = station::<PickupStation>(&mut app);
+        assert_eq!(app.world().get::<Transform>(moved).unwrap().translation, Vec3::new(-300.0, 200.0, 0.0));
+        assert_eq!(app.world().get::<ReservedStation>(robot).unwrap().0, None);
+        assert_eq!(*app.world().get::<RobotState>(robot).unwrap(), RobotState::Idle);
+        assert_eq!(transitions(&mut app), [(RobotId(0), RobotState::MovingToPickup, RobotState::Idle)]);
+    }
+
+    #[test]
+    fn new_bays_start_empty_and_their_robots_let_go() {
+        let mut app = warehouse();
+        let dropoff = station::<DropoffStation>(&mut app);
+        let robot = send(&mut app, 0, dropoff, RobotState::DroppingOff);
+
+        reload(&mut app, |config| config.dropoff_stations[0].bays = vec![(-30.0, 0.0), (30.0, 0.0)]);
+
+        let bays = app.world().get::<Bays>(dropoff).unwrap();
+        assert_eq!(bays.docks, [Vec3::new(270.0, 0.0, 0.0), Vec3::new(330.0, 0.0, 0.0)]);
+        assert_eq!(bays.free(), 2);
+        assert_eq!(app.world().get::<ReservedStation>(robot).unwrap().0, None);
+        assert_eq!(*app.world().get::<RobotState>(robot).unwrap(), RobotState::WaitingForDropoff);
+    }
+
+    #[test]
+    fn a_retired_robot_hands_its_order_back() {
+        let mut app = warehouse();
+        let (pickup, dropoff) = (station::<PickupStation>(&mut app), station::<DropoffStation>(&mut app));
+        let order = Order { id: OrderId(7), pickup, dropoff, priority: 2, created: 1.0, assigned: Some(3.0) };
+        send(&mut app, 0, pickup, RobotState::MovingToPickup);
+        let carrier = send(&mut app, 1, dropoff, RobotState::MovingToDropoff);
+        app.world_mut().get_mut::<AssignedOrder>(carrier).unwrap().0 = Some(order);
+
+        // Both robots are busy, so the newest goes
+        reload(&mut app, |config| config.robot_count = 1);
+
+        assert!(app.world().get_entity(carrier).is_err());
+        assert_eq!(app.world().get::<Bays>(dropoff).unwrap().free(), 1);
+        assert_eq!(app.world().resource::<OrderQueue>().pending, [Order { assigned: None, ..order }]);
+    }
+
+    #[test]
+    fn more_robots_join_after_the_last_id() {
+        let mut app = warehouse();
+
+        reload(&mut app, |config| config.robot_count = 4);
+
+        let mut ids: Vec<u32> = app.world_mut().query_filtered::<&RobotId, With<Robot>>().iter(app.world()).map(|id| id.0).collect();
+        ids.sort();
+        assert_eq!(ids, [0, 1, 2, 3]);
+    }
+}
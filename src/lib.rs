@@ -0,0 +1,25 @@
+//! Warehouse swarm simulation: robots picking, dropping off and charging,
+//! packaged as a Bevy plugin so it can be embedded in other apps and test harnesses.
+
+pub mod avoidance;
+pub mod batch;
+pub mod cli;
+pub mod collisions;
+pub mod config;
+pub mod dispatch;
+pub mod events;
+pub mod export;
+pub mod components;
+pub mod hot_reload;
+pub mod metrics;
+pub mod navigation;
+pub mod orders;
+pub mod plugin;
+pub mod replay;
+pub mod resources;
+pub mod spatial;
+pub mod systems;
+pub mod utilityfunctions;
+pub mod watchdog;
+
+pub use plugin::{SwarmSimPlugin, SwarmSimSet};
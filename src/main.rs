@@ -1,97 +1,231 @@
 use bevy::prelude::*;
-use ron::de::from_reader;
-use std::fs::File;
 use bevy::diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin};
+use bevy::app::ScheduleRunnerPlugin;
 use std::time::Duration;
 
+use bevy_ecs_sim::batch::check_run_end;
+use bevy_ecs_sim::cli::{self, CliArgs, CliError, RunMode};
+use bevy_ecs_sim::config;
+use bevy_ecs_sim::events::{EventLog, EventLogPlugin};
+use bevy_ecs_sim::export::{ExportPlugin, Exporter};
+use bevy_ecs_sim::hot_reload::HotReloadPlugin;
+use bevy_ecs_sim::replay::{RecordPlugin, Recorder, Recording, Replay, ReplayPlugin};
+use bevy_ecs_sim::resources::{RunSettings, SimulationConfig};
+use bevy_ecs_sim::{SwarmSimPlugin, SwarmSimSet};
 
-#[cfg(feature = "headless")]
-use bevy::app::ScheduleRunnerPlugin;
-
-mod components;
-mod systems;
-mod resources;
-mod utilityfunctions;
+fn main() -> AppExit {
+    // 1. Parse the command line
+    let args = match CliArgs::parse() {
+        Ok(args) => args,
+        Err(CliError::HelpRequested) => {
+            println!("{}", cli::USAGE);
+            return AppExit::Success;
+        }
+        Err(err) => {
+            eprintln!("error: {err}\n\n{}", cli::USAGE);
+            return AppExit::error();
+        }
+    };
 
-use systems::*;
-use resources::SimulationConfig;
+    // Replays come with their own config and skip the simulation entirely
+    if let Some(path) = &args.replay {
+        return run_replay(&args, path);
+    }
 
-fn main() {
-    // 1. Load the Config File from disk
-    let config_path = "assets/simulation.ron";
-    let file = File::open(config_path).expect("Failed to open config file");
-    let config: SimulationConfig = from_reader(file).expect("Failed to parse config file");
+    // 2. Load and validate the Config File from disk
+    let mut config = match config::load_config(&args.scenario) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("error: {err}");
+            return AppExit::error();
+        }
+    };
+    args.apply_overrides(&mut config);
 
     println!("Loaded Config: {:?}", config);
+    println!("Run: seed {}, output '{}'{}{}", args.seed, args.output_dir.display(),
+        if args.deterministic { ", deterministic" } else { "" },
+        if args.batch { ", batch" } else { "" });
+
+    // 3. Keep a copy of the effective config next to the run output
+    if let Err(err) = save_effective_config(&args, &config) {
+        eprintln!("error: failed to write to output directory '{}': {err}", args.output_dir.display());
+        return AppExit::error();
+    }
+
+    // 4. Open the export files and event log up front so a bad output directory fails before the run
+    let exporter = match args.sample_interval.map(|interval| Exporter::create(&args.output_dir, interval)) {
+        Some(Ok(exporter)) => Some(exporter),
+        Some(Err(err)) => {
+            eprintln!("error: failed to create export files in '{}': {err}", args.output_dir.display());
+            return AppExit::error();
+        }
+        None => None,
+    };
+    let event_log = match args.event_log.then(|| EventLog::open(&args.output_dir)) {
+        Some(Ok(log)) => Some(log),
+        Some(Err(err)) => {
+            eprintln!("error: failed to open event log in '{}': {err}", args.output_dir.display());
+            return AppExit::error();
+        }
+        None => None,
+    };
+    let recorder = match &args.record {
+        Some(path) => match Recorder::create(path, args.seed) {
+            Ok(recorder) => Some(recorder),
+            Err(err) => {
+                eprintln!("error: failed to create recording '{}': {err}", path.display());
+                return AppExit::error();
+            }
+        },
+        None => None,
+    };
 
     let mut app = App::new();
 
     // ========================================================================
     // PART A: CHOOSE THE ENGINE MODE
     // ========================================================================
-    
-    // OPTION 1: VISUAL MODE (Default)
-    // Runs if you DO NOT pass the 'headless' feature flag.
-    #[cfg(not(feature = "headless"))] 
-    {
-        app.add_plugins(DefaultPlugins);
-    }
-
-    // OPTION 2: HEADLESS MODE (Server/CI/WSL Fix)
-    // Runs only if you use --features headless
-    #[cfg(feature = "headless")]
-    {
-        // MinimalPlugins gives you the Event Loop + Time + ECS.
-        // It does NOT give you a Window, GPU Renderer, or Audio.
-        app.add_plugins(MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(
-            // We force the loop to run at 60 ticks per second.
-            // Without this, Headless mode runs at 100% CPU speed (10,000+ FPS),
-            // which is great for stress testing but hard to read logs.
-            Duration::from_secs_f64(0.0),
-        )));
-        
-        // Note: If you use AssetServer later, you might need:
-        // app.add_plugins(AssetPlugin::default());
-    }
+    add_engine_plugins(&mut app, args.mode);
 
     // ========================================================================
     // PART B: ADD COMMON RESOURCES & PLUGINS
     // ========================================================================
-    app.insert_resource(config)
-        .insert_resource(Time::<Fixed>::from_hz(60.0))
-       .add_plugins(FrameTimeDiagnosticsPlugin::default())
-       .add_plugins(LogDiagnosticsPlugin::default());
+    app.insert_resource(RunSettings {
+            duration: args.duration,
+            target_deliveries: args.target_deliveries,
+            seed: args.seed,
+            output_dir: args.output_dir.clone(),
+        })
+       .add_plugins(SwarmSimPlugin::new(config)
+            .with_camera(args.mode == RunMode::Visual)
+            .with_seed(args.seed)
+            .deterministic(args.deterministic))
+       .add_plugins(ExportPlugin)
+       .add_plugins(EventLogPlugin)
+       .add_plugins(RecordPlugin);
+
+    if let Some(exporter) = exporter {
+        app.insert_resource(exporter);
+    }
+    if let Some(log) = event_log {
+        app.insert_resource(log);
+    }
+    if let Some(recorder) = recorder {
+        app.insert_resource(recorder);
+    }
+
+    // Batch runs only report at the end
+    if !args.batch {
+        add_diagnostics(&mut app);
+    }
+
+    // Live edits would make a deterministic run impossible to reproduce
+    if !args.deterministic {
+        app.add_plugins(HotReloadPlugin {
+            path: args.scenario.clone(),
+            overrides: args.clone(),
+        });
+    }
 
     // ========================================================================
-    // PART C: ADD YOUR SYSTEMS
+    // PART C: ADD RUN CONTROL SYSTEMS
     // ========================================================================
-    app.add_systems(Startup, setup_simulation)
-       .add_systems(FixedUpdate, (
-            movement_system, 
-            robot_state_machine, 
-            battery_system
-       ))
-       .add_systems(Update, 
+    app.add_systems(FixedUpdate, check_run_end.after(SwarmSimSet::Metrics));
+
+    // 5. Launch
+    app.run()
+}
+
+/// Plays a recording back frame by frame. Headless replays run to the end (or `--duration`) and print the final frame.
+fn run_replay(args: &CliArgs, path: &std::path::Path) -> AppExit {
+    let recording = match Recording::load(path) {
+        Ok(recording) if recording.frames.is_empty() => {
+            eprintln!("error: recording '{}' holds no frames", path.display());
+            return AppExit::error();
+        }
+        Ok(recording) => recording,
+        Err(err) => {
+            eprintln!("error: {err}");
+            return AppExit::error();
+        }
+    };
+
+    println!("Replaying '{}': seed {}, {} frames, {:.1}s simulated{}", path.display(), recording.seed,
+        recording.frames.len(), recording.duration(),
+        if recording.truncated { " (file ends mid-frame, the rest is lost)" } else { "" });
+
+    let mut app = App::new();
+    add_engine_plugins(&mut app, args.mode);
+
+    let headless = args.mode == RunMode::Headless;
+    app.add_plugins(ReplayPlugin {
+            tick_rate_hz: recording.tick_rate_hz,
+            spawn_camera: !headless,
+            exit_at_end: headless,
+            fast_forward: headless,
+        })
+       .insert_resource(Replay::new(recording, args.seek, args.duration));
+
+    if !headless {
+        add_diagnostics(&mut app);
+    }
+
+    app.run()
+}
+
+fn add_engine_plugins(app: &mut App, mode: RunMode) {
+    match mode {
+        // OPTION 1: VISUAL MODE (Default)
+        // Not compiled into builds with the 'headless' feature flag.
+        RunMode::Visual => {
+            #[cfg(not(feature = "headless"))]
+            app.add_plugins(DefaultPlugins);
+        }
+
+        // OPTION 2: HEADLESS MODE (Server/CI/WSL Fix)
+        // Selected with --headless, or the default with --features headless
+        RunMode::Headless => {
+            // MinimalPlugins gives you the Event Loop + Time + ECS.
+            // It does NOT give you a Window, GPU Renderer, or Audio.
+            app.add_plugins(MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(
+                // No wait between ticks: headless mode runs at 100% CPU speed
+                // (10,000+ FPS), which is great for stress testing.
+                Duration::from_secs_f64(0.0),
+            )));
+
+            // Note: If you use AssetServer later, you might need:
+            // app.add_plugins(AssetPlugin::default());
+        }
+    }
+}
+
+fn add_diagnostics(app: &mut App) {
+    app.add_plugins(FrameTimeDiagnosticsPlugin::default())
+       .add_plugins(LogDiagnosticsPlugin::default())
+       .add_systems(Update,
             log_performance
             // camera_controls
         );
-    
-    
+}
 
-    // 3. Launch
-    app.run();
+fn save_effective_config(args: &CliArgs, config: &SimulationConfig) -> std::io::Result<()> {
+    std::fs::create_dir_all(&args.output_dir)?;
+    let pretty = ron::ser::PrettyConfig::default();
+    let serialized = ron::ser::to_string_pretty(config, pretty).map_err(std::io::Error::other)?;
+    std::fs::write(args.output_dir.join("config.ron"), serialized)
 }
 
 // A manual system to print performance metrics
 fn log_performance (time: Res<Time>, mut frame_count: Local<u32>) {
     *frame_count += 1;
     // Only print every 1000 frames to avoid spamming console IO
-    if *frame_count % 1000 == 0 {
+    if frame_count.is_multiple_of(1000) {
         // Calculate raw FPS (1.0 / delta_seconds)
         let fps = 1.0 / time.delta_secs();
-        println!("🚀 Simulation Speed: {:.2} Ticks Per Second (Delta: {:.4}ms)", 
-            fps, 
+        println!("🚀 Simulation Speed: {:.2} Ticks Per Second (Delta: {:.4}ms)",
+            fps,
             time.delta_secs() * 1000.0
         );
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,502 @@
+use bevy::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::components::*;
+use crate::collisions::{Collider, CollisionDetected};
+use crate::events::RobotStateChanged;
+use crate::orders::{OrderCompleted, OrderQueue};
+use crate::watchdog::{StallDetected, StallKind};
+
+const STATE_COUNT: usize = RobotState::ALL.len();
+
+/// Running totals describing the work the fleet has done so far.
+/// Updated every tick; call `report()` for the derived KPIs.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct Metrics {
+    pub sim_time: f32,
+    pub deliveries_completed: u64,
+    pub deaths: u64,
+    pub deadlocks: u64,
+    pub livelocks: u64,
+    pub blocked: u64, // stalls of a single robot
+    pub robot_collisions: u64,
+    pub obstacle_collisions: u64,
+    pub orders_created: u64,
+    pub orders_pending: u64,   // arrived but not yet taken by a robot
+    pub orders_cancelled: u64,
+    pub cycle_times: Vec<f32>, // pickup arrival to dropoff finished, in simulated seconds
+    pub order_latencies: Vec<f32>, // order arrival to dropoff finished
+    pub order_waits: Vec<f32>,     // order arrival to a robot taking it
+    pub charger_busy_secs: f32, // summed over all charger bays
+    pub charger_capacity_secs: f32, // charger bay count integrated over time
+    pub robots: BTreeMap<RobotId, RobotMetrics>,
+    pub stations: BTreeMap<StationId, StationMetrics>, // stations with a queue
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct StationMetrics {
+    pub queue_length_secs: f32, // robots in line integrated over time
+    pub queue_length_max: usize,
+    pub waits: Vec<f32>,        // time in line of each robot the station was handed to
+    tracked_secs: f32,
+    joined: BTreeMap<RobotId, f32>, // robots in line and when they joined
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct RobotMetrics {
+    pub time_in_state: [f32; STATE_COUNT], // indexed by RobotState::index()
+    pub distance: f32,
+    pub deliveries: u64,
+    pub cycle_start: Option<f32>,
+    pub battery: BatteryHealth, // as of the last tick
+    last_position: Option<Vec3>,
+}
+
+impl Metrics {
+    /// Marks the start of a delivery cycle (robot arrived at its pickup).
+    /// A cycle interrupted by charging keeps its original start.
+    pub fn start_cycle(&mut self, robot: RobotId, now: f32) {
+        let entry = self.robots.entry(robot).or_default();
+        entry.cycle_start.get_or_insert(now);
+    }
+
+    /// Counts a finished delivery and closes the robot's cycle.
+    pub fn record_delivery(&mut self, robot: RobotId, now: f32) {
+        let entry = self.robots.entry(robot).or_default();
+        entry.deliveries += 1;
+        if let Some(start) = entry.cycle_start.take() {
+            self.cycle_times.push(now - start);
+        }
+        self.deliveries_completed += 1;
+    }
+
+    pub fn deliveries_per_hour(&self) -> f32 {
+        let hours = self.sim_time / 3600.0;
+        if hours > 0.0 { self.deliveries_completed as f32 / hours } else { 0.0 }
+    }
+
+    pub fn cycle_time_mean(&self) -> f32 {
+        mean(&self.cycle_times)
+    }
+
+    /// Share of robot time spent on delivery work, over the whole fleet.
+    pub fn fleet_utilization(&self) -> f32 {
+        let fleet_time = self.fleet_time();
+        ratio(productive_time(&fleet_time), fleet_time.iter().sum())
+    }
+
+    /// Share of charger bay time spent booked.
+    pub fn charger_occupancy(&self) -> f32 {
+        ratio(self.charger_busy_secs, self.charger_capacity_secs)
+    }
+
+    pub fn distance_travelled(&self) -> f32 {
+        // Summing no robots at all would give -0.0, which prints as "-0"
+        self.robots.values().fold(0.0, |total, robot| total + robot.distance)
+    }
+
+    /// State of health of the batteries in use, % of nominal capacity.
+    pub fn battery_health_mean(&self) -> f32 {
+        let healths: Vec<f32> = self.robots.values().map(|robot| robot.battery.health).collect();
+        mean(&healths)
+    }
+
+    pub fn battery_replacements(&self) -> u64 {
+        self.robots.values().map(|robot| robot.battery.replacements as u64).sum()
+    }
+
+    // Time each state took, summed over all robots
+    fn fleet_time(&self) -> [f32; STATE_COUNT] {
+        let mut fleet_time = [0.0; STATE_COUNT];
+        for robot in self.robots.values() {
+            for (total, time) in fleet_time.iter_mut().zip(robot.time_in_state) {
+                *total += time;
+            }
+        }
+        fleet_time
+    }
+
+    /// Every KPI, with percentiles over all cycle times and order latencies. Sorts copies of them, so it is meant
+    /// for the end of a run rather than every tick.
+    pub fn report(&self) -> MetricsReport {
+        let mut cycles = self.cycle_times.clone();
+        cycles.sort_by(f32::total_cmp);
+        let mut latencies = self.order_latencies.clone();
+        latencies.sort_by(f32::total_cmp);
+
+        let robots: Vec<RobotReport> = self.robots.iter().map(|(id, robot)| {
+            let tracked: f32 = robot.time_in_state.iter().sum();
+            RobotReport {
+                id: id.0,
+                deliveries: robot.deliveries,
+                distance: robot.distance,
+                utilization: ratio(productive_time(&robot.time_in_state), tracked),
+                state_share: state_shares(&robot.time_in_state),
+                battery_health: robot.battery.health,
+                battery_cycles: robot.battery.cycles,
+                depth_of_discharge_mean: ratio(robot.battery.cycles, robot.battery.charges as f32),
+                battery_replacements: robot.battery.replacements,
+            }
+        }).collect();
+
+        MetricsReport {
+            sim_time_secs: self.sim_time,
+            deliveries_completed: self.deliveries_completed,
+            deliveries_per_hour: self.deliveries_per_hour(),
+            cycle_time_mean: mean(&cycles),
+            cycle_time_p50: percentile(&cycles, 0.50),
+            cycle_time_p90: percentile(&cycles, 0.90),
+            cycle_time_p99: percentile(&cycles, 0.99),
+            fleet_utilization: self.fleet_utilization(),
+            fleet_state_share: state_shares(&self.fleet_time()),
+            charger_occupancy: self.charger_occupancy(),
+            distance_travelled: self.distance_travelled(),
+            deaths: self.deaths,
+            deadlocks: self.deadlocks,
+            livelocks: self.livelocks,
+            blocked: self.blocked,
+            robot_collisions: self.robot_collisions,
+            obstacle_collisions: self.obstacle_collisions,
+            orders_created: self.orders_created,
+            orders_completed: self.order_latencies.len() as u64,
+            orders_pending: self.orders_pending,
+            orders_cancelled: self.orders_cancelled,
+            order_latency_mean: mean(&latencies),
+            order_latency_p50: percentile(&latencies, 0.50),
+            order_latency_p90: percentile(&latencies, 0.90),
+            order_latency_p99: percentile(&latencies, 0.99),
+            order_wait_mean: mean(&self.order_waits),
+            battery_health_mean: self.battery_health_mean(),
+            battery_health_min: robots.iter().map(|robot| robot.battery_health).reduce(f32::min).unwrap_or(0.0),
+            battery_replacements: self.battery_replacements(),
+            robots,
+            stations: self.stations.iter().map(|(id, station)| {
+                let mut waits = station.waits.clone();
+                waits.sort_by(f32::total_cmp);
+                StationReport {
+                    id: id.0,
+                    queue_length_mean: ratio(station.queue_length_secs, station.tracked_secs),
+                    queue_length_max: station.queue_length_max,
+                    served_from_queue: waits.len() as u64,
+                    queue_wait_mean: mean(&waits),
+                    queue_wait_p90: percentile(&waits, 0.90),
+                    queue_wait_max: waits.last().copied().unwrap_or(0.0),
+                }
+            }).collect(),
+        }
+    }
+}
+
+// --- REPORT ---
+
+/// KPIs derived from `Metrics`, ready to print or serialize.
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricsReport {
+    pub sim_time_secs: f32,
+    pub deliveries_completed: u64,
+    pub deliveries_per_hour: f32,
+    pub cycle_time_mean: f32,
+    pub cycle_time_p50: f32,
+    pub cycle_time_p90: f32,
+    pub cycle_time_p99: f32,
+    pub fleet_utilization: f32, // share of robot time spent on delivery work
+    pub fleet_state_share: BTreeMap<String, f32>,
+    pub charger_occupancy: f32, // share of charger bay time spent booked
+    pub distance_travelled: f32,
+    pub deaths: u64,
+    pub deadlocks: u64,
+    pub livelocks: u64,
+    pub blocked: u64,
+    pub robot_collisions: u64,
+    pub obstacle_collisions: u64,
+    pub orders_created: u64,
+    pub orders_completed: u64,
+    pub orders_pending: u64,
+    pub orders_cancelled: u64,
+    pub order_latency_mean: f32, // order arrival to dropoff finished
+    pub order_latency_p50: f32,
+    pub order_latency_p90: f32,
+    pub order_latency_p99: f32,
+    pub order_wait_mean: f32,    // order arrival to a robot taking it
+    pub battery_health_mean: f32, // state of health, % of nominal capacity
+    pub battery_health_min: f32,
+    pub battery_replacements: u64,
+    pub robots: Vec<RobotReport>,
+    pub stations: Vec<StationReport>, // stations with a queue
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StationReport {
+    pub id: u32,
+    pub queue_length_mean: f32, // robots in line, averaged over time
+    pub queue_length_max: usize,
+    pub served_from_queue: u64,
+    pub queue_wait_mean: f32,   // joining the line to getting the station
+    pub queue_wait_p90: f32,
+    pub queue_wait_max: f32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RobotReport {
+    pub id: u32,
+    pub deliveries: u64,
+    pub distance: f32,
+    pub utilization: f32,
+    pub state_share: BTreeMap<String, f32>,
+    pub battery_health: f32,          // % of nominal capacity left
+    pub battery_cycles: f32,          // equivalent full cycles of the battery in use
+    pub depth_of_discharge_mean: f32, // per charge, 1.0 = 100%
+    pub battery_replacements: u32,
+}
+
+impl fmt::Display for MetricsReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "📊 Metrics after {:.1}s simulated", self.sim_time_secs)?;
+        writeln!(f, "   Deliveries: {} ({:.1}/h)", self.deliveries_completed, self.deliveries_per_hour)?;
+        writeln!(f, "   Cycle time: mean {:.2}s, p50 {:.2}s, p90 {:.2}s, p99 {:.2}s",
+            self.cycle_time_mean, self.cycle_time_p50, self.cycle_time_p90, self.cycle_time_p99)?;
+        writeln!(f, "   Fleet utilization: {:.1}%, charger occupancy: {:.1}%",
+            self.fleet_utilization * 100.0, self.charger_occupancy * 100.0)?;
+        writeln!(f, "   Distance travelled: {:.0}, deaths: {}", self.distance_travelled, self.deaths)?;
+        writeln!(f, "   Stalls: {} deadlocks, {} livelocks, {} blocked", self.deadlocks, self.livelocks, self.blocked)?;
+        writeln!(f, "   Collisions: {} robot-robot, {} robot-obstacle", self.robot_collisions, self.obstacle_collisions)?;
+        if self.orders_created > 0 {
+            writeln!(f, "   Orders: {} created, {} completed, {} pending, {} cancelled",
+                self.orders_created, self.orders_completed, self.orders_pending, self.orders_cancelled)?;
+            writeln!(f, "   Order latency: mean {:.2}s, p50 {:.2}s, p90 {:.2}s, p99 {:.2}s (mean wait for a robot {:.2}s)",
+                self.order_latency_mean, self.order_latency_p50, self.order_latency_p90, self.order_latency_p99, self.order_wait_mean)?;
+        }
+
+        writeln!(f, "   Battery health: mean {:.2}%, min {:.2}%, {} replacements",
+            self.battery_health_mean, self.battery_health_min, self.battery_replacements)?;
+
+        write!(f, "   Time by state:")?;
+        for state in RobotState::ALL {
+            let share = self.fleet_state_share.get(&format!("{state:?}")).copied().unwrap_or(0.0);
+            if share > 0.0 {
+                write!(f, " {state:?} {:.1}%", share * 100.0)?;
+            }
+        }
+        writeln!(f)?;
+
+        for robot in &self.robots {
+            writeln!(f, "   Robot {:>3}: {:>5} deliveries, {:>9.0} distance, {:>5.1}% utilized, battery {:>6.2}% health after {:.1} cycles",
+                robot.id, robot.deliveries, robot.distance, robot.utilization * 100.0, robot.battery_health, robot.battery_cycles)?;
+        }
+        for station in &self.stations {
+            writeln!(f, "   Station {:>3}: queue mean {:.2}, max {}, {} served from it, wait mean {:.2}s, p90 {:.2}s, max {:.2}s",
+                station.id, station.queue_length_mean, station.queue_length_max, station.served_from_queue,
+                station.queue_wait_mean, station.queue_wait_p90, station.queue_wait_max)?;
+        }
+        Ok(())
+    }
+}
+
+fn productive_time(time_in_state: &[f32; STATE_COUNT]) -> f32 {
+    RobotState::ALL.iter()
+        .filter(|state| state.is_productive())
+        .map(|state| time_in_state[state.index()])
+        .sum()
+}
+
+fn state_shares(time_in_state: &[f32; STATE_COUNT]) -> BTreeMap<String, f32> {
+    let total: f32 = time_in_state.iter().sum();
+    RobotState::ALL.iter()
+        .map(|state| (format!("{state:?}"), ratio(time_in_state[state.index()], total)))
+        .collect()
+}
+
+fn mean(values: &[f32]) -> f32 {
+    if values.is_empty() { 0.0 } else { values.iter().sum::<f32>() / values.len() as f32 }
+}
+
+fn ratio(part: f32, total: f32) -> f32 {
+    if total > 0.0 { part / total } else { 0.0 }
+}
+
+// Nearest-rank percentile of an already sorted slice
+fn percentile(sorted: &[f32], p: f32) -> f32 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (p * sorted.len() as f32).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+// --- SYSTEM ---
+
+/// Integrates per-state time, distance, charger occupancy and queue lengths once per tick, keeps each battery's health,
+/// and counts cycles, deliveries, deaths and waits in line from the tick's state transitions, stalls the watchdog found,
+/// collisions and completed orders.
+#[allow(clippy::too_many_arguments)]
+pub fn metrics_system(
+    time: Res<Time>,
+    mut metrics: ResMut<Metrics>,
+    mut transitions: MessageReader<RobotStateChanged>,
+    mut stalls: MessageReader<StallDetected>,
+    mut collisions: MessageReader<CollisionDetected>,
+    mut completed: MessageReader<OrderCompleted>,
+    orders: Res<OrderQueue>,
+    robot_query: Query<(&RobotId, &Transform, &RobotState, &BatteryHealth), With<Robot>>,
+    charger_query: Query<&Bays, With<ChargerStation>>,
+    queue_query: Query<(&StationId, &StationQueue)>
+)
+{
+    let dt = time.delta_secs();
+    metrics.sim_time = time.elapsed_secs();
+
+    for (id, transform, state, health) in &robot_query {
+        let robot = metrics.robots.entry(*id).or_default();
+        robot.time_in_state[state.index()] += dt;
+        robot.battery = *health;
+
+        if let Some(last) = robot.last_position {
+            robot.distance += last.distance(transform.translation);
+        }
+        robot.last_position = Some(transform.translation);
+    }
+
+    for event in transitions.read() {
+        match (event.from, event.to) {
+            (_, RobotState::PickingUp) => metrics.start_cycle(event.robot, event.sim_time),
+            (RobotState::DroppingOff, RobotState::Idle) => metrics.record_delivery(event.robot, event.sim_time),
+            (_, RobotState::Dead) => metrics.deaths += 1,
+            _ => {}
+        }
+
+        // Waits in line, from joining to being sent to the station. Robots leaving the line otherwise aren't counted
+        let Some(station) = event.station else { continue };
+        if event.to.is_queued() {
+            metrics.stations.entry(station).or_default().joined.insert(event.robot, event.sim_time);
+        } else if event.from.is_queued()
+            && let Some(station) = metrics.stations.get_mut(&station)
+            && let Some(joined) = station.joined.remove(&event.robot)
+            && event.to.is_moving()
+        {
+            station.waits.push(event.sim_time - joined);
+        }
+    }
+
+    for stall in stalls.read() {
+        match stall.kind {
+            StallKind::Deadlock => metrics.deadlocks += 1,
+            StallKind::Livelock => metrics.livelocks += 1,
+            StallKind::Blocked => metrics.blocked += 1,
+        }
+    }
+
+    for collision in collisions.read() {
+        match collision.other {
+            Collider::Robot(_) => metrics.robot_collisions += 1,
+            Collider::Obstacle(_) => metrics.obstacle_collisions += 1,
+        }
+    }
+
+    for order in completed.read() {
+        metrics.order_latencies.push(order.sim_time - order.created);
+        metrics.order_waits.push(order.assigned - order.created);
+    }
+    metrics.orders_created = orders.created;
+    metrics.orders_pending = orders.pending.len() as u64;
+    metrics.orders_cancelled = orders.cancelled;
+
+    let chargers: usize = charger_query.iter().map(|bays| bays.docks.len()).sum();
+    let busy: usize = charger_query.iter().map(|bays| bays.docks.len() - bays.free()).sum();
+    metrics.charger_busy_secs += busy as f32 * dt;
+    metrics.charger_capacity_secs += chargers as f32 * dt;
+
+    for (id, queue) in &queue_query {
+        if queue.spots.is_empty() { continue; }
+        let station = metrics.stations.entry(*id).or_default();
+        station.queue_length_secs += queue.line.len() as f32 * dt;
+        station.queue_length_max = station.queue_length_max.max(queue.line.len());
+        station.tracked_secs += dt;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn tracked() -> App {
+        let mut app = App::new();
+        app.insert_resource(Time::<()>::default())
+            .init_resource::<Metrics>()
+            .init_resource::<OrderQueue>()
+            .add_message::<RobotStateChanged>()
+            .add_message::<StallDetected>()
+            .add_message::<CollisionDetected>()
+            .add_message::<OrderCompleted>()
+            .add_systems(Update, metrics_system);
+        app
+    }
+
+    fn tick(app: &mut App, secs: f32) {
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs_f32(secs));
+        app.update();
+    }
+
+    #[test]
+    fn time_distance_and_charger_use_add_up_tick_by_tick() {
+        let mut app = tracked();
+        let robot = app.world_mut().spawn((Robot, RobotId(0), Transform::default(), RobotState::Idle, BatteryHealth::default())).id();
+        let charger = app.world_mut().spawn((ChargerStation, Bays::new(vec![Vec3::ZERO, Vec3::X]))).id();
+
+        // A second idle, then three driving 100 a second with one of the two charger bays taken
+        tick(&mut app, 1.0);
+        *app.world_mut().get_mut::<RobotState>(robot).unwrap() = RobotState::MovingToPickup;
+        app.world_mut().get_mut::<Bays>(charger).unwrap().book(robot, Vec3::ZERO);
+        for step in 1..=3 {
+            app.world_mut().get_mut::<Transform>(robot).unwrap().translation.x = step as f32 * 100.0;
+            tick(&mut app, 1.0);
+        }
+
+        let metrics = app.world().resource::<Metrics>();
+        let time_in_state = metrics.robots[&RobotId(0)].time_in_state;
+        assert_eq!(time_in_state[RobotState::Idle.index()], 1.0);
+        assert_eq!(time_in_state[RobotState::MovingToPickup.index()], 3.0);
+        assert_eq!(time_in_state.iter().sum::<f32>(), 4.0);
+
+        let report = metrics.report();
+        assert_eq!(report.sim_time_secs, 4.0);
+        assert_eq!(report.distance_travelled, 300.0);
+        assert_eq!(report.fleet_utilization, 0.75);
+        assert_eq!(report.fleet_state_share["Idle"], 0.25);
+        assert_eq!(report.fleet_state_share["MovingToPickup"], 0.75);
+        assert_eq!(report.robots[0].utilization, 0.75);
+        assert_eq!(report.charger_occupancy, 3.0 / 8.0);
+    }
+
+    #[test]
+    fn cycle_times_come_out_as_mean_and_nearest_rank_percentiles() {
+        let mut metrics = Metrics { sim_time: 1800.0, ..default() };
+        for cycle in 1..=10 {
+            let start = cycle as f32 * 100.0;
+            metrics.start_cycle(RobotId(0), start);
+            // Stopping to charge mid-cycle keeps the first start
+            metrics.start_cycle(RobotId(0), start + 5.0);
+            metrics.record_delivery(RobotId(0), start + cycle as f32 * 10.0);
+        }
+
+        let report = metrics.report();
+        assert_eq!(report.deliveries_completed, 10);
+        assert_eq!(report.deliveries_per_hour, 20.0);
+        assert_eq!(report.cycle_time_mean, 55.0);
+        assert_eq!(report.cycle_time_p50, 50.0);
+        assert_eq!(report.cycle_time_p90, 90.0);
+        assert_eq!(report.cycle_time_p99, 100.0);
+        assert_eq!(report.robots[0].deliveries, 10);
+    }
+
+    #[test]
+    fn an_empty_run_reports_zeros() {
+        let report = Metrics::default().report();
+        assert_eq!(report.deliveries_per_hour, 0.0);
+        assert_eq!(report.cycle_time_p90, 0.0);
+        assert_eq!(report.fleet_utilization, 0.0);
+        assert_eq!(report.charger_occupancy, 0.0);
+    }
+}
@@ -0,0 +1,764 @@
+use bevy::platform::collections::{HashMap, HashSet};
+use bevy::prelude::*;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+use std::f32::consts::SQRT_2;
+
+use crate::components::*;
+use crate::resources::{NavigationMode, SimulationConfig};
+
+pub const NAV_CELL_SIZE: f32 = ROBOT_SIZE / 2.0;
+pub const CLEARANCE: f32 = ROBOT_SIZE / 2.0 + 5.0; // robot radius plus a little margin
+const MAP_MARGIN: f32 = 200.0; // free space planned around the outermost station or obstacle
+const WAYPOINT_RADIUS: f32 = NAV_CELL_SIZE;
+const REPLAN_AFTER: f32 = 1.0; // seconds without getting closer to the next waypoint
+const MIN_PROGRESS: f32 = 1.0; // distance a robot must close to count as moving on
+const SCHEDULE_SLACK: f32 = ROBOT_SIZE; // how far behind its schedule a robot may fall before its reservations are void
+const FOOTPRINT: i32 = 2; // cells around a reserved cell that no other robot may use at the same step
+const WINDOW: u32 = 16; // steps ahead a cooperative plan reserves, re-planned when half of them are used up
+const MAX_EXPANSIONS: usize = 20_000; // cooperative searches give up after this many states
+
+// A* step costs, scaled so diagonals stay integers
+const STRAIGHT_COST: u32 = 10;
+const DIAGONAL_COST: u32 = 14;
+
+const NEIGHBOURS: [IVec2; 8] = [
+    IVec2::new(1, 0), IVec2::new(-1, 0), IVec2::new(0, 1), IVec2::new(0, -1),
+    IVec2::new(1, 1), IVec2::new(1, -1), IVec2::new(-1, 1), IVec2::new(-1, -1),
+];
+
+// --- PATH ---
+
+/// Waypoints from the robot to its `TargetPosition`, planned on the `NavGrid`.
+#[derive(Component, Debug, Clone)]
+pub struct NavPath {
+    pub waypoints: VecDeque<Vec3>, // next waypoint first, the last one is the goal itself
+    pub goal: Option<Vec3>,        // target the waypoints lead to, None until planned
+    pub times: VecDeque<f32>,      // sim time each waypoint is due, cooperative navigation only
+    pub replan: bool,              // set when the robot stopped making progress
+    closest: f32,
+    stalled_secs: f32,
+}
+
+impl Default for NavPath {
+    fn default() -> Self {
+        Self {
+            waypoints: VecDeque::new(),
+            goal: None,
+            times: VecDeque::new(),
+            replan: false,
+            closest: f32::INFINITY,
+            stalled_secs: 0.0,
+        }
+    }
+}
+
+impl NavPath {
+    pub fn set(&mut self, goal: Vec3, waypoints: impl IntoIterator<Item = Vec3>) {
+        self.set_scheduled(goal, waypoints, []);
+    }
+
+    pub fn set_scheduled(&mut self, goal: Vec3, waypoints: impl IntoIterator<Item = Vec3>, times: impl IntoIterator<Item = f32>) {
+        self.waypoints = waypoints.into_iter().collect();
+        self.times = times.into_iter().collect();
+        self.goal = Some(goal);
+        self.replan = false;
+        self.closest = f32::INFINITY;
+        self.stalled_secs = 0.0;
+    }
+
+    /// Drops the waypoints the robot has reached (never the goal) and returns the one to head for.
+    pub fn next_waypoint(&mut self, position: Vec3) -> Option<Vec3> {
+        while self.waypoints.len() > 1 && self.waypoints[0].distance(position) < WAYPOINT_RADIUS {
+            self.waypoints.pop_front();
+            self.closest = f32::INFINITY;
+        }
+        self.waypoints.front().copied()
+    }
+
+    /// Drops the waypoints whose time has passed (never the goal) and returns the next one with the time it's due.
+    pub fn next_scheduled(&mut self, now: f32) -> Option<(Vec3, f32)> {
+        while self.waypoints.len() > 1 && self.times.front().is_some_and(|&due| due <= now) {
+            self.waypoints.pop_front();
+            self.times.pop_front();
+        }
+        Some((*self.waypoints.front()?, self.times.front().copied().unwrap_or(now)))
+    }
+
+    /// Moves `position` toward the next scheduled waypoint so it arrives when due rather than as early as it can.
+    /// Asks for a new plan if the robot has fallen too far behind for its reservations to mean anything.
+    pub fn follow_schedule(&mut self, position: Vec3, now: f32, dt: f32, max_speed: f32) -> Vec3 {
+        let Some((waypoint, due)) = self.next_scheduled(now) else { return position };
+        let remaining = waypoint.distance(position);
+        let step = if due > now { remaining * (dt / (due - now)).min(1.0) } else { remaining };
+        let step = step.min(max_speed * dt);
+        if remaining - step > SCHEDULE_SLACK {
+            self.replan = true;
+        }
+        if step > 0.0001 { position + (waypoint - position).normalize() * step } else { position }
+    }
+
+    /// Asks for a new plan once the robot has spent `REPLAN_AFTER` seconds without closing in on its waypoint.
+    pub fn track_progress(&mut self, distance: f32, dt: f32) {
+        if distance < self.closest - MIN_PROGRESS {
+            self.closest = distance;
+            self.stalled_secs = 0.0;
+            return;
+        }
+        self.stalled_secs += dt;
+        if self.stalled_secs > REPLAN_AFTER {
+            self.replan = true;
+            self.closest = f32::INFINITY;
+            self.stalled_secs = 0.0;
+        }
+    }
+}
+
+// --- GRID ---
+
+/// Occupancy grid over the map: a cell is blocked when a robot centred there would touch an obstacle.
+/// Everything outside the grid counts as open floor.
+#[derive(Resource, Debug, Default)]
+pub struct NavGrid {
+    origin: Vec2, // corner of cell (0, 0)
+    size: IVec2,
+    blocked: Vec<bool>,
+}
+
+impl NavGrid {
+    pub fn build(config: &SimulationConfig) -> Self {
+        let shapes: Vec<ObstacleShape> = config.obstacles.iter().flat_map(|obstacle| obstacle.shapes()).collect();
+
+        // Bounds of everything placed on the map
+        let mut points: Vec<Vec2> = [&config.pickup_stations, &config.dropoff_stations, &config.charger_stations]
+            .into_iter()
+            .flatten()
+            .flat_map(|station| station.queue_spots().chain(station.docks()).chain([Vec2::from(station.position)]))
+            .collect();
+        for shape in &shapes {
+            match *shape {
+                ObstacleShape::Box { center, half_size } => points.extend([center - half_size, center + half_size]),
+                ObstacleShape::Segment { start, end, .. } => points.extend([start, end]),
+            }
+        }
+        let min = points.iter().copied().reduce(Vec2::min).unwrap_or(Vec2::ZERO) - MAP_MARGIN;
+        let max = points.iter().copied().reduce(Vec2::max).unwrap_or(Vec2::ZERO) + MAP_MARGIN;
+
+        let size = ((max - min) / NAV_CELL_SIZE).ceil().as_ivec2().max(IVec2::ONE);
+        let mut grid = NavGrid { origin: min, size, blocked: vec![false; (size.x * size.y) as usize] };
+        for y in 0..size.y {
+            for x in 0..size.x {
+                let center = grid.center(IVec2::new(x, y));
+                grid.blocked[(y * size.x + x) as usize] = shapes.iter()
+                    .any(|shape| shape.distance_and_normal(center).0 < CLEARANCE);
+            }
+        }
+        grid
+    }
+
+    pub fn cell_of(&self, point: Vec2) -> IVec2 {
+        ((point - self.origin) / NAV_CELL_SIZE).floor().as_ivec2()
+    }
+
+    pub fn center(&self, cell: IVec2) -> Vec2 {
+        self.origin + (cell.as_vec2() + 0.5) * NAV_CELL_SIZE
+    }
+
+    fn index(&self, cell: IVec2) -> Option<usize> {
+        let inside = cell.x >= 0 && cell.y >= 0 && cell.x < self.size.x && cell.y < self.size.y;
+        inside.then(|| (cell.y * self.size.x + cell.x) as usize)
+    }
+
+    pub fn is_blocked(&self, cell: IVec2) -> bool {
+        self.index(cell).is_some_and(|index| self.blocked[index])
+    }
+
+    /// Whether a robot can drive straight from `from` to `to`. The cells at either end are never
+    /// treated as blocked, so robots parked next to a rack can still leave and stations next to one be reached.
+    pub fn line_of_sight(&self, from: Vec2, to: Vec2, avoid: &HashSet<IVec2>) -> bool {
+        let (from_cell, to_cell) = (self.cell_of(from), self.cell_of(to));
+        let steps = (from.distance(to) / (NAV_CELL_SIZE * 0.25)).ceil() as usize;
+        (0..=steps).all(|step| {
+            let cell = self.cell_of(from.lerp(to, step as f32 / steps.max(1) as f32));
+            cell == from_cell || cell == to_cell || !(self.is_blocked(cell) || avoid.contains(&cell))
+        })
+    }
+
+    /// Plans a path with A* and shortens it to the corners that matter.
+    /// Returns the waypoints after `start`, ending exactly at `goal`, or None when the goal can't be reached.
+    pub fn find_path(&self, start: Vec2, goal: Vec2, avoid: &HashSet<IVec2>) -> Option<Vec<Vec2>> {
+        if self.line_of_sight(start, goal, avoid) {
+            return Some(vec![goal]);
+        }
+
+        // Ends outside the grid are planned from the nearest cell on its edge
+        let clamp = |cell: IVec2| cell.clamp(IVec2::ZERO, self.size - 1);
+        let (start_cell, goal_cell) = (clamp(self.cell_of(start)), clamp(self.cell_of(goal)));
+        let (start_index, goal_index) = (self.index(start_cell)?, self.index(goal_cell)?);
+
+        let heuristic = |cell: IVec2| {
+            let delta = (cell - goal_cell).abs();
+            let (long, short) = (delta.max_element() as u32, delta.min_element() as u32);
+            STRAIGHT_COST * (long - short) + DIAGONAL_COST * short
+        };
+        let passable = |cell: IVec2| {
+            cell == goal_cell || self.index(cell).is_some_and(|index| !self.blocked[index] && !avoid.contains(&cell))
+        };
+
+        let mut cost = vec![u32::MAX; self.blocked.len()];
+        let mut came_from = vec![usize::MAX; self.blocked.len()];
+        // Ties on estimated total broken by distance left, then cell index, so every run expands the same cells
+        let mut open = BinaryHeap::new();
+        cost[start_index] = 0;
+        open.push(Reverse((heuristic(start_cell), heuristic(start_cell), start_index)));
+
+        while let Some(Reverse((_, _, index))) = open.pop() {
+            if index == goal_index {
+                break;
+            }
+            let cell = IVec2::new(index as i32 % self.size.x, index as i32 / self.size.x);
+
+            for offset in NEIGHBOURS {
+                let next = cell + offset;
+                let diagonal = offset.x != 0 && offset.y != 0;
+                // No cutting corners past a blocked cell
+                if !passable(next) || (diagonal && !(passable(cell + IVec2::new(offset.x, 0)) && passable(cell + IVec2::new(0, offset.y)))) {
+                    continue;
+                }
+                let Some(next_index) = self.index(next) else { continue };
+                let next_cost = cost[index] + if diagonal { DIAGONAL_COST } else { STRAIGHT_COST };
+                if next_cost < cost[next_index] {
+                    cost[next_index] = next_cost;
+                    came_from[next_index] = index;
+                    open.push(Reverse((next_cost + heuristic(next), heuristic(next), next_index)));
+                }
+            }
+        }
+        if cost[goal_index] == u32::MAX {
+            return None;
+        }
+
+        // Walk back from the goal, then swap the end cells for the real start and goal positions
+        let mut corners = vec![goal];
+        let mut index = came_from[goal_index];
+        while index != start_index && index != usize::MAX {
+            corners.push(self.center(IVec2::new(index as i32 % self.size.x, index as i32 / self.size.x)));
+            index = came_from[index];
+        }
+        corners.push(start);
+        corners.reverse();
+
+        // String pulling: from each corner, jump to the furthest one still in sight
+        let mut waypoints = Vec::new();
+        let mut anchor = 0;
+        while anchor < corners.len() - 1 {
+            let next = (anchor + 2..corners.len()).rev()
+                .find(|&candidate| self.line_of_sight(corners[anchor], corners[candidate], avoid))
+                .unwrap_or(anchor + 1);
+            waypoints.push(corners[next]);
+            anchor = next;
+        }
+        Some(waypoints)
+    }
+
+    /// Steps from every cell to the goal's cell around the obstacles, ignoring robots (`u32::MAX` where it can't be reached).
+    pub fn distance_field(&self, goal: Vec2) -> Vec<u32> {
+        let goal_cell = self.cell_of(goal);
+        let passable = |cell: IVec2| cell == goal_cell || !self.is_blocked(cell);
+        let mut distances = vec![u32::MAX; self.blocked.len()];
+        let Some(goal_index) = self.index(goal_cell) else { return distances };
+        let mut queue = VecDeque::from([goal_cell]);
+        distances[goal_index] = 0;
+        while let Some(cell) = queue.pop_front() {
+            let distance = distances[self.index(cell).unwrap_or(goal_index)];
+            for offset in NEIGHBOURS {
+                let next = cell + offset;
+                let diagonal = offset.x != 0 && offset.y != 0;
+                if !passable(next) || (diagonal && !(passable(cell + IVec2::new(offset.x, 0)) && passable(cell + IVec2::new(0, offset.y)))) {
+                    continue;
+                }
+                if let Some(index) = self.index(next)
+                    && distances[index] == u32::MAX
+                {
+                    distances[index] = distance + 1;
+                    queue.push_back(next);
+                }
+            }
+        }
+        distances
+    }
+
+    /// Windowed cooperative A* over (cell, step): every step a robot moves to a neighbouring cell or waits,
+    /// never coming within `FOOTPRINT` cells of a cell another robot has reserved for that step.
+    /// Only the next `WINDOW` steps are planned; past that, the distance left around the obstacles is the estimate.
+    /// `distances` is the goal's `distance_field`.
+    /// Returns the states after the start and whether they end at the goal, where the robot can stay from then on,
+    /// or None when every way out runs into another robot within `MAX_EXPANSIONS`.
+    pub fn find_cooperative_path(&self, robot: RobotId, start: Vec2, goal: Vec2, distances: &[u32], start_step: u32, reservations: &ReservationTable) -> Option<(Vec<(IVec2, u32)>, bool)> {
+        let (start_cell, goal_cell) = (self.cell_of(start), self.cell_of(goal));
+        let passable = |cell: IVec2| cell == start_cell || cell == goal_cell || !self.is_blocked(cell);
+
+        // Off the grid there's nothing in the way: walk to its edge, then follow the distance field
+        let heuristic = |cell: IVec2| match self.index(goal_cell) {
+            Some(_) => {
+                let edge = cell.clamp(IVec2::ZERO, self.size - 1);
+                let to_edge = (cell - edge).abs().max_element() as u32;
+                self.index(edge).map_or(u32::MAX, |index| distances[index].saturating_add(to_edge))
+            }
+            None => (cell - goal_cell).abs().max_element() as u32,
+        };
+
+        // Every move takes one step, so the first time a state is reached is the earliest it can be
+        let mut came_from: HashMap<(IVec2, u32), (IVec2, u32)> = HashMap::default();
+        let mut open = BinaryHeap::new();
+        came_from.insert((start_cell, start_step), (start_cell, start_step));
+        open.push(Reverse((heuristic(start_cell), heuristic(start_cell), start_cell.x, start_cell.y, start_step)));
+
+        let mut expanded = 0;
+        while let Some(Reverse((_, _, x, y, step))) = open.pop() {
+            let cell = IVec2::new(x, y);
+            // This close, the robot counts as arrived: it may stop for good here, but not drive through
+            if (cell - goal_cell).abs().max_element() <= 1 {
+                if reservations.can_stay(robot, goal_cell, step) {
+                    return Some((walk_back(&came_from, (cell, step), start_step), true));
+                }
+                if step > start_step {
+                    continue;
+                }
+            }
+            // The end of a partial plan is held until the next one, so it has to be somewhere nobody else is due
+            if step - start_step >= WINDOW {
+                if reservations.can_stay(robot, cell, step) {
+                    return Some((walk_back(&came_from, (cell, step), start_step), false));
+                }
+                continue;
+            }
+            expanded += 1;
+            if expanded > MAX_EXPANSIONS {
+                return None;
+            }
+
+            for offset in std::iter::once(IVec2::ZERO).chain(NEIGHBOURS) {
+                let next = (cell + offset, step + 1);
+                let diagonal = offset.x != 0 && offset.y != 0;
+                if !passable(next.0) || (diagonal && !(passable(cell + IVec2::new(offset.x, 0)) && passable(cell + IVec2::new(0, offset.y)))) {
+                    continue;
+                }
+                let h = heuristic(next.0);
+                if h == u32::MAX || came_from.contains_key(&next) || !reservations.is_free(robot, next.0, next.1) {
+                    continue;
+                }
+                came_from.insert(next, (cell, step));
+                open.push(Reverse((next.1 - start_step + h, h, next.0.x, next.0.y, next.1)));
+            }
+        }
+        None
+    }
+}
+
+// States from just after `start_step` up to `state`, in order
+fn walk_back(came_from: &HashMap<(IVec2, u32), (IVec2, u32)>, mut state: (IVec2, u32), start_step: u32) -> Vec<(IVec2, u32)> {
+    let mut states = Vec::new();
+    while state.1 > start_step {
+        states.push(state);
+        state = came_from[&state];
+    }
+    states.reverse();
+    states
+}
+
+// --- RESERVATIONS ---
+
+/// Space-time reservations for cooperative navigation: which cell each robot will be in at which step,
+/// and where robots that aren't moving (or are at the end of their plan) stay until they plan again.
+#[derive(Resource, Debug, Default)]
+pub struct ReservationTable {
+    visits: HashMap<IVec2, Vec<(u32, RobotId)>>, // planned (step, robot) visits, by cell
+    holds: HashMap<IVec2, Vec<(u32, RobotId)>>,  // (from step, robot) staying in a cell indefinitely
+    robots: HashMap<RobotId, Reserved>,
+}
+
+/// Everything one robot has reserved, so it can be released or put back.
+#[derive(Debug, Default, Clone)]
+pub struct Reserved {
+    visits: Vec<(IVec2, u32)>,
+    hold: Option<(IVec2, u32)>,
+}
+
+impl ReservationTable {
+    pub fn clear(&mut self) {
+        self.visits.clear();
+        self.holds.clear();
+        self.robots.clear();
+    }
+
+    /// Forgets visits that are already in the past.
+    pub fn prune(&mut self, now_step: u32) {
+        self.visits.retain(|_, visits| {
+            visits.retain(|(step, _)| *step >= now_step);
+            !visits.is_empty()
+        });
+        for reserved in self.robots.values_mut() {
+            reserved.visits.retain(|(_, step)| *step >= now_step);
+        }
+    }
+
+    pub fn reserve(&mut self, robot: RobotId, states: impl IntoIterator<Item = (IVec2, u32)>) {
+        let reserved = self.robots.entry(robot).or_default();
+        for (cell, step) in states {
+            self.visits.entry(cell).or_default().push((step, robot));
+            reserved.visits.push((cell, step));
+        }
+    }
+
+    pub fn hold(&mut self, robot: RobotId, cell: IVec2, from_step: u32) {
+        self.holds.entry(cell).or_default().push((from_step, robot));
+        self.robots.entry(robot).or_default().hold = Some((cell, from_step));
+    }
+
+    pub fn held_cell(&self, robot: RobotId) -> Option<IVec2> {
+        self.robots.get(&robot).and_then(|reserved| reserved.hold).map(|(cell, _)| cell)
+    }
+
+    /// Drops everything the robot has reserved and hands it back, to `restore` if the new plan falls through.
+    pub fn release(&mut self, robot: RobotId) -> Reserved {
+        let reserved = self.robots.remove(&robot).unwrap_or_default();
+        for (cell, _) in &reserved.visits {
+            if let Some(visits) = self.visits.get_mut(cell) {
+                visits.retain(|(_, other)| *other != robot);
+                if visits.is_empty() {
+                    self.visits.remove(cell);
+                }
+            }
+        }
+        if let Some((cell, _)) = reserved.hold
+            && let Some(holds) = self.holds.get_mut(&cell)
+        {
+            holds.retain(|(_, other)| *other != robot);
+            if holds.is_empty() {
+                self.holds.remove(&cell);
+            }
+        }
+        reserved
+    }
+
+    pub fn restore(&mut self, robot: RobotId, reserved: Reserved) {
+        self.reserve(robot, reserved.visits);
+        if let Some((cell, from_step)) = reserved.hold {
+            self.hold(robot, cell, from_step);
+        }
+    }
+
+    /// Robots other than `robot` that are due near `cell` at or after `step`, or will stop near it.
+    pub fn due_near(&self, robot: RobotId, cell: IVec2, step: u32) -> impl Iterator<Item = RobotId> + '_ {
+        Self::footprint(cell).flat_map(move |near| {
+            let visits = self.visits.get(&near).into_iter().flatten().filter(move |(at, _)| *at >= step);
+            let holds = self.holds.get(&near).into_iter().flatten();
+            visits.chain(holds).map(|(_, other)| *other)
+        })
+        .filter(move |other| *other != robot)
+    }
+
+    fn footprint(cell: IVec2) -> impl Iterator<Item = IVec2> {
+        (-FOOTPRINT..=FOOTPRINT).flat_map(move |dy| (-FOOTPRINT..=FOOTPRINT).map(move |dx| cell + IVec2::new(dx, dy)))
+    }
+
+    /// Whether `robot` may be in `cell` at `step` without coming too close to anyone else.
+    pub fn is_free(&self, robot: RobotId, cell: IVec2, step: u32) -> bool {
+        Self::footprint(cell).all(|near| {
+            let visited = self.visits.get(&near)
+                .is_some_and(|visits| visits.iter().any(|&(at, other)| at == step && other != robot));
+            let held = self.holds.get(&near)
+                .is_some_and(|holds| holds.iter().any(|&(from, other)| from <= step && other != robot));
+            !visited && !held
+        })
+    }
+
+    /// Whether `robot` can stop in `cell` from `step` on, with nobody else due nearby afterwards.
+    pub fn can_stay(&self, robot: RobotId, cell: IVec2, step: u32) -> bool {
+        Self::footprint(cell).all(|near| {
+            let visited = self.visits.get(&near)
+                .is_some_and(|visits| visits.iter().any(|&(at, other)| at >= step && other != robot));
+            let held = self.holds.get(&near)
+                .is_some_and(|holds| holds.iter().any(|&(_, other)| other != robot));
+            !visited && !held
+        })
+    }
+}
+
+// --- SYSTEMS ---
+
+/// Rebuilds the grid from the map and drops every plan made on the old one.
+pub fn rebuild_nav_grid(
+    config: Res<SimulationConfig>,
+    mut nav: ResMut<NavGrid>,
+    mut reservations: ResMut<ReservationTable>,
+    mut path_query: Query<&mut NavPath>
+)
+{
+    *nav = NavGrid::build(&config);
+    reservations.clear();
+    for mut path in &mut path_query {
+        path.goal = None;
+    }
+}
+
+/// Plans a path for every moving robot whose target changed, or that asked to re-plan.
+/// Re-plans also steer around robots that are parked, working or dead, since those won't move out of the way.
+pub fn plan_paths(
+    config: Res<SimulationConfig>,
+    nav: Res<NavGrid>,
+    mut robot_query: Query<(&Transform, &TargetPosition, &RobotState, &mut NavPath), With<Robot>>
+)
+{
+    if config.navigation != NavigationMode::Decentralized {
+        return;
+    }
+
+    let mut parked: HashSet<IVec2> = HashSet::default();
+    if robot_query.iter().any(|(_, _, state, path)| state.is_moving() && path.replan) {
+        let reach = (ROBOT_SIZE / NAV_CELL_SIZE).ceil() as i32;
+        for (transform, _, state, _) in &robot_query {
+            if state.is_moving() { continue; }
+            let cell = nav.cell_of(transform.translation.truncate());
+            for dy in -reach..=reach {
+                for dx in -reach..=reach {
+                    parked.insert(cell + IVec2::new(dx, dy));
+                }
+            }
+        }
+    }
+
+    let no_robots = HashSet::default();
+    for (transform, target, state, mut path) in &mut robot_query {
+        if !state.is_moving() || (path.goal == Some(target.0) && !path.replan) {
+            continue;
+        }
+        let avoid = if path.replan { &parked } else { &no_robots };
+        let start = transform.translation.truncate();
+        let goal = target.0.truncate();
+
+        // Nothing found (e.g. boxed in by parked robots): head straight for the goal and try again later
+        let waypoints = nav.find_path(start, goal, avoid).unwrap_or_else(|| vec![goal]);
+        path.set(target.0, waypoints.into_iter().map(|point| point.extend(target.0.z)));
+    }
+}
+
+// Per-tick working sets of `plan_cooperative_paths`, kept between ticks for their allocations
+#[derive(Default)]
+pub struct PlanningScratch {
+    moving: Vec<(RobotId, Entity)>,  // sorted by id
+    queue: VecDeque<(RobotId, Entity)>,
+    disturbed: HashSet<RobotId>,     // robots someone stopped in the way of
+    attempts: HashMap<RobotId, u32>,
+    distance_fields: HashMap<IVec2, Vec<u32>>, // by goal cell; targets are stations, so there are only a handful
+}
+
+const MAX_ATTEMPTS: u32 = 3; // plans per robot per tick, so robots disturbing each other can't go round in circles
+
+type PlanningRobot = (Entity, &'static RobotId, &'static Transform, &'static TargetPosition, &'static RobotState, &'static mut NavPath);
+
+/// Cooperative navigation: robots that need a path plan one at a time in id order, each around the reservations
+/// of the robots before it, then reserve their own. Robots that aren't moving hold the cell they're in.
+pub fn plan_cooperative_paths(
+    time: Res<Time>,
+    config: Res<SimulationConfig>,
+    nav: Res<NavGrid>,
+    mut reservations: ResMut<ReservationTable>,
+    mut robot_query: Query<PlanningRobot, With<Robot>>,
+    mut scratch: Local<PlanningScratch>
+)
+{
+    if config.navigation != NavigationMode::Cooperative {
+        return;
+    }
+    let PlanningScratch { moving, queue, disturbed, attempts, distance_fields } = &mut *scratch;
+    if nav.is_changed() {
+        distance_fields.clear();
+    }
+
+    // A step is long enough to cross a cell diagonally at full speed
+    let step_secs = (NAV_CELL_SIZE * SQRT_2 / config.robot_speed) as f64;
+    let now = time.elapsed_secs_f64();
+    let now_step = (now / step_secs).ceil() as u32;
+    reservations.prune(now_step);
+
+    // 1. Robots standing still hold where they are. One that stopped somewhere it didn't plan to
+    // (out of battery, or waiting for a charger) is in the way of plans made around it
+    disturbed.clear();
+    for (_, id, transform, _, state, _) in &robot_query {
+        let cell = nav.cell_of(transform.translation.truncate());
+        if !state.is_moving() && reservations.held_cell(*id) != Some(cell) {
+            reservations.release(*id);
+            disturbed.extend(reservations.due_near(*id, cell, now_step));
+            reservations.hold(*id, cell, now_step);
+        }
+    }
+
+    // 2. Queue the robots that need a plan, in id order so the same run always hands out the same reservations
+    moving.clear();
+    queue.clear();
+    attempts.clear();
+    for (entity, id, _, target, state, path) in &robot_query {
+        if !state.is_moving() {
+            continue;
+        }
+        moving.push((*id, entity));
+        // Partial plans are extended once half their window is used up
+        let window_ending = path.waypoints.back() != Some(&target.0)
+            && path.times.back().is_none_or(|&end| ((end as f64 - now) / step_secs) < (WINDOW / 2) as f64);
+        if path.goal != Some(target.0) || path.replan || window_ending || disturbed.contains(id) {
+            queue.push_back((*id, entity));
+        }
+    }
+    moving.sort_unstable();
+    queue.make_contiguous().sort_unstable();
+
+    // 3. Plan each around the reservations of the robots before it
+    let due = |step: u32| (step as f64 * step_secs) as f32;
+    while let Some((id, entity)) = queue.pop_front() {
+        let Ok((_, _, transform, target, _, mut path)) = robot_query.get_mut(entity) else { continue };
+        let tries = attempts.entry(id).or_default();
+        if *tries >= MAX_ATTEMPTS {
+            continue;
+        }
+        *tries += 1;
+        let start = nav.cell_of(transform.translation.truncate());
+
+        let previous = reservations.release(id);
+        let goal = target.0.truncate();
+        let distances = distance_fields.entry(nav.cell_of(goal)).or_insert_with(|| nav.distance_field(goal));
+        let Some((states, arrives)) = nav.find_cooperative_path(id, transform.translation.truncate(), goal, distances, now_step, &reservations) else {
+            // No safe way out from here yet. An old plan nobody stopped in the way of is still safe while it lasts,
+            // so keep to it. Otherwise stay put, and whoever was due here plans again.
+            if path.times.back().is_some_and(|&end| end as f64 > now) && !disturbed.contains(&id) {
+                reservations.restore(id, previous);
+            } else {
+                let mut due_here: Vec<RobotId> = reservations.due_near(id, start, now_step).collect();
+                due_here.sort_unstable();
+                due_here.dedup();
+                for other in due_here {
+                    if let Ok(index) = moving.binary_search_by_key(&other, |(id, _)| *id) {
+                        disturbed.insert(other);
+                        queue.push_back(moving[index]);
+                    }
+                }
+                reservations.hold(id, start, now_step);
+                path.set_scheduled(target.0, [transform.translation], [now as f32]);
+            }
+            // Try again on the next tick
+            path.replan = true;
+            continue;
+        };
+        reservations.reserve(id, std::iter::once((start, now_step)).chain(states.iter().copied()));
+
+        // Cell centres along the way, and the target itself if the plan gets there
+        let mut waypoints: Vec<Vec3> = states.iter().map(|(cell, _)| nav.center(*cell).extend(target.0.z)).collect();
+        let mut times: Vec<f32> = states.iter().map(|(_, step)| due(*step)).collect();
+        let (end, end_step) = states.last().copied().unwrap_or((start, now_step));
+        if arrives {
+            reservations.hold(id, nav.cell_of(target.0.truncate()), end_step);
+            if waypoints.is_empty() {
+                times.push(due(now_step + 1));
+                waypoints.push(target.0);
+            }
+            if let Some(last) = waypoints.last_mut() {
+                *last = target.0;
+            }
+        } else {
+            // A partial plan ends where the robot can wait for the next one
+            reservations.hold(id, end, end_step);
+        }
+        path.set_scheduled(target.0, waypoints, times);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::ObstacleDef;
+
+    fn grid(obstacles: Vec<ObstacleDef>) -> NavGrid {
+        NavGrid::build(&SimulationConfig { obstacles, ..SimulationConfig::for_tests() })
+    }
+
+    // A rack standing across the x axis, from y = -200 to 200
+    fn wall() -> ObstacleDef {
+        ObstacleDef::Rect { min: (-20.0, -200.0), max: (20.0, 200.0) }
+    }
+
+    #[test]
+    fn line_of_sight_stops_at_walls() {
+        let nav = grid(vec![wall()]);
+        let avoid = HashSet::default();
+        assert!(!nav.line_of_sight(Vec2::new(-150.0, 0.0), Vec2::new(150.0, 0.0), &avoid));
+        assert!(nav.line_of_sight(Vec2::new(-150.0, 260.0), Vec2::new(150.0, 260.0), &avoid));
+        assert!(nav.line_of_sight(Vec2::new(-150.0, -100.0), Vec2::new(-150.0, 100.0), &avoid));
+
+        // Cells to avoid block the view like walls
+        let avoid: HashSet<IVec2> = [nav.cell_of(Vec2::new(0.0, 260.0))].into_iter().collect();
+        assert!(!nav.line_of_sight(Vec2::new(-150.0, 260.0), Vec2::new(150.0, 260.0), &avoid));
+    }
+
+    #[test]
+    fn finds_a_path_around_a_wall() {
+        let nav = grid(vec![wall()]);
+        let avoid = HashSet::default();
+        let (start, goal) = (Vec2::new(-150.0, 0.0), Vec2::new(150.0, 0.0));
+
+        let waypoints = nav.find_path(start, goal, &avoid).expect("a way around the rack");
+        assert!(waypoints.len() >= 2, "the path turns around the rack: {waypoints:?}");
+        assert_eq!(waypoints.last(), Some(&goal));
+        let mut from = start;
+        for &to in &waypoints {
+            assert!(nav.line_of_sight(from, to, &avoid), "{from} to {to} cuts through the rack");
+            from = to;
+        }
+    }
+
+    #[test]
+    fn finds_no_path_into_a_closed_room() {
+        let room = ObstacleDef::Polyline {
+            points: vec![(-100.0, -100.0), (100.0, -100.0), (100.0, 100.0), (-100.0, 100.0), (-100.0, -100.0)],
+            thickness: 10.0,
+        };
+        let nav = grid(vec![room]);
+        assert_eq!(nav.find_path(Vec2::new(-250.0, 0.0), Vec2::ZERO, &HashSet::default()), None);
+    }
+
+    // Where a robot is at `step`: the last planned state by then, or where it started
+    fn cell_at(start: IVec2, states: &[(IVec2, u32)], step: u32) -> IVec2 {
+        states.iter().take_while(|(_, at)| *at <= step).last().map_or(start, |(cell, _)| *cell)
+    }
+
+    #[test]
+    fn cooperative_robots_keep_apart() {
+        let nav = grid(Vec::new());
+        let mut reservations = ReservationTable::default();
+
+        // Two robots crossing at the origin, each planned around the one before it, as plan_cooperative_paths does
+        let trips = [
+            (RobotId(0), Vec2::new(-100.0, 0.0), Vec2::new(100.0, 0.0)),
+            (RobotId(1), Vec2::new(0.0, -100.0), Vec2::new(0.0, 100.0)),
+        ];
+        let mut plans = Vec::new();
+        for (robot, start, goal) in trips {
+            let distances = nav.distance_field(goal);
+            let (states, arrives) = nav.find_cooperative_path(robot, start, goal, &distances, 0, &reservations)
+                .expect("a plan around the other robot");
+            assert!(arrives, "{robot:?} gets to its goal within the window");
+            let start_cell = nav.cell_of(start);
+            reservations.reserve(robot, std::iter::once((start_cell, 0)).chain(states.iter().copied()));
+            let (end, end_step) = states.last().copied().unwrap_or((start_cell, 0));
+            reservations.hold(robot, end, end_step);
+            plans.push((start_cell, states));
+        }
+
+        let last_step = plans.iter().filter_map(|(_, states)| states.last()).map(|(_, step)| *step).max().unwrap();
+        for step in 0..=last_step + 1 {
+            let first = cell_at(plans[0].0, &plans[0].1, step);
+            let second = cell_at(plans[1].0, &plans[1].1, step);
+            assert!((first - second).abs().max_element() > FOOTPRINT, "robots meet at step {step}: {first} and {second}");
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use bevy::prelude::*;
+use rand::Rng;
+use serde::Serialize;
+
+use crate::components::*;
+use crate::resources::{OrderDef, OrderSource, SimRng, SimulationConfig};
+use crate::utilityfunctions::entities_in_id_order;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct OrderId(pub u64);
+
+/// A delivery from one station to another, waiting in the `OrderQueue` or carried by a robot.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Order {
+    pub id: OrderId,
+    pub pickup: Entity,
+    pub dropoff: Entity,
+    pub priority: u8,          // higher goes first
+    pub created: f32,          // sim time the order arrived
+    pub assigned: Option<f32>, // sim time a robot took it
+}
+
+impl Order {
+    /// The order as taken by a robot at `now`.
+    pub fn assign(self, now: f32) -> Order {
+        Order { assigned: Some(now), ..self }
+    }
+}
+
+/// The order a robot is working on, from taking it until it is dropped off.
+#[derive(Component, Debug, Default, Clone, Copy)]
+pub struct AssignedOrder(pub Option<Order>);
+
+/// Sent when a robot finishes dropping off an order.
+#[derive(Message, Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct OrderCompleted {
+    pub order: OrderId,
+    pub robot: RobotId,
+    pub pickup: Option<StationId>,
+    pub dropoff: Option<StationId>, // the station it was actually dropped at
+    pub priority: u8,
+    pub created: f32,
+    pub assigned: f32,
+    pub sim_time: f32,
+}
+
+/// Orders that arrived and haven't been taken yet, and where the generator is up to.
+#[derive(Resource, Debug, Default)]
+pub struct OrderQueue {
+    pub pending: Vec<Order>,
+    pub created: u64,
+    pub cancelled: u64,          // dropped because a station they need was removed
+    source: Option<OrderSource>, // the generator the state below belongs to
+    next_arrival: Option<f32>,   // Poisson: sim time of the next order
+    schedule: Vec<OrderDef>,     // Schedule: the orders still to come, latest first
+}
+
+impl OrderQueue {
+    /// The orders that go first (highest priority, then oldest) at each pickup, as many as `free` bays it has, in that same order.
+    pub fn offers(&self, free: impl Fn(Entity) -> usize) -> Vec<Order> {
+        let mut offers: Vec<Order> = Vec::new();
+        let mut pending: Vec<&Order> = self.pending.iter().collect();
+        pending.sort_by_key(|order| (std::cmp::Reverse(order.priority), order.id));
+        for order in pending {
+            if offers.iter().filter(|offer| offer.pickup == order.pickup).count() < free(order.pickup) {
+                offers.push(*order);
+            }
+        }
+        offers
+    }
+
+    /// Removes a pending order, for the robot that takes it.
+    pub fn take(&mut self, id: OrderId) -> Option<Order> {
+        let index = self.pending.iter().position(|order| order.id == id)?;
+        Some(self.pending.remove(index))
+    }
+
+    /// Puts an order a robot gave up back in line, keeping its id and arrival time.
+    pub fn requeue(&mut self, order: Order) {
+        self.pending.push(Order { assigned: None, ..order });
+    }
+
+    /// Drops the pending orders `keep` rejects, counting them as cancelled.
+    pub fn cancel_unless(&mut self, keep: impl FnMut(&Order) -> bool) {
+        let before = self.pending.len();
+        self.pending.retain(keep);
+        self.cancelled += (before - self.pending.len()) as u64;
+    }
+
+    // Starts generating from `source`. After a reload, scheduled orders already in the past are skipped
+    fn restart(&mut self, source: &OrderSource, now: f32) {
+        let since = if self.source.is_some() { now } else { 0.0 };
+        self.source = Some(source.clone());
+        self.next_arrival = None;
+        self.schedule = match source {
+            OrderSource::Schedule(orders) => orders.iter().filter(|order| order.time >= since).copied().collect(),
+            _ => Vec::new(),
+        };
+        self.schedule.sort_by(|a, b| b.time.total_cmp(&a.time));
+    }
+
+    fn push(&mut self, pickup: Entity, dropoff: Entity, priority: u8, created: f32) {
+        self.pending.push(Order { id: OrderId(self.created), pickup, dropoff, priority, created, assigned: None });
+        self.created += 1;
+    }
+}
+
+// --- SYSTEM ---
+
+/// Adds the orders that arrived this tick to the `OrderQueue`. Station indexes pick the n-th pickup
+/// or dropoff station in id order, so orders keep working after stations are added by a reload.
+/// `OrderSource::Unlimited` generates nothing: robots then work without orders.
+pub fn generate_orders(
+    time: Res<Time>,
+    config: Res<SimulationConfig>,
+    mut rng: ResMut<SimRng>,
+    mut queue: ResMut<OrderQueue>,
+    pickup_query: Query<(Entity, &StationId), With<PickupStation>>,
+    dropoff_query: Query<(Entity, &StationId), With<DropoffStation>>
+)
+{
+    let now = time.elapsed_secs();
+    if queue.source.as_ref() != Some(&config.orders) {
+        queue.restart(&config.orders, now);
+    }
+
+    let pickups = entities_in_id_order(pickup_query.iter().map(|(e, id)| (e, *id)));
+    let dropoffs = entities_in_id_order(dropoff_query.iter().map(|(e, id)| (e, *id)));
+    if pickups.is_empty() || dropoffs.is_empty() { return; }
+
+    match config.orders {
+        OrderSource::Poisson { per_hour } => {
+            // Exponential gaps between arrivals, each order between two random stations
+            let rate = per_hour / 3600.0;
+            let next_gap = |rng: &mut SimRng| -(1.0 - rng.0.random::<f32>()).ln() / rate;
+            let mut arrival = match queue.next_arrival {
+                Some(arrival) => arrival,
+                None => now + next_gap(&mut rng),
+            };
+            while arrival <= now {
+                let pickup = pickups[rng.0.random_range(0..pickups.len())];
+                let dropoff = dropoffs[rng.0.random_range(0..dropoffs.len())];
+                queue.push(pickup, dropoff, 0, arrival);
+                arrival += next_gap(&mut rng);
+            }
+            queue.next_arrival = Some(arrival);
+        }
+        OrderSource::Schedule(_) => {
+            while queue.schedule.last().is_some_and(|order| order.time <= now) {
+                let Some(order) = queue.schedule.pop() else { break };
+                // A station removed by a reload takes its orders with it
+                match (pickups.get(order.pickup), dropoffs.get(order.dropoff)) {
+                    (Some(&pickup), Some(&dropoff)) => queue.push(pickup, dropoff, order.priority, order.time),
+                    _ => queue.cancelled += 1,
+                }
+            }
+        }
+        // load_config turns the file into a Schedule before the run starts
+        OrderSource::Unlimited | OrderSource::Csv(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stations(count: usize) -> Vec<Entity> {
+        let mut world = World::new();
+        (0..count).map(|_| world.spawn_empty().id()).collect()
+    }
+
+    fn ids(orders: &[Order]) -> Vec<u64> {
+        orders.iter().map(|order| order.id.0).collect()
+    }
+
+    #[test]
+    fn offers_go_by_priority_then_age() {
+        let [pickup, dropoff] = stations(2)[..] else { unreachable!() };
+        let mut queue = OrderQueue::default();
+        for (priority, created) in [(0, 1.0), (2, 2.0), (0, 3.0), (2, 4.0), (1, 5.0)] {
+            queue.push(pickup, dropoff, priority, created);
+        }
+        assert_eq!(ids(&queue.offers(|_| 10)), vec![1, 3, 4, 0, 2]);
+    }
+
+    #[test]
+    fn offers_stop_at_the_free_bays_of_each_pickup() {
+        let [first, second, full, dropoff] = stations(4)[..] else { unreachable!() };
+        let mut queue = OrderQueue::default();
+        for (pickup, priority) in [(first, 0), (second, 0), (first, 1), (full, 2), (second, 0), (first, 0)] {
+            queue.push(pickup, dropoff, priority, 0.0);
+        }
+        let free = |pickup: Entity| if pickup == first { 2 } else if pickup == second { 1 } else { 0 };
+        assert_eq!(ids(&queue.offers(free)), vec![2, 0, 1]);
+    }
+
+    #[test]
+    fn a_requeued_order_keeps_its_place() {
+        let [pickup, dropoff] = stations(2)[..] else { unreachable!() };
+        let mut queue = OrderQueue::default();
+        queue.push(pickup, dropoff, 0, 1.0);
+        queue.push(pickup, dropoff, 0, 2.0);
+
+        let taken = queue.take(OrderId(0)).unwrap().assign(3.0);
+        queue.requeue(taken);
+        assert_eq!(ids(&queue.offers(|_| 1)), vec![0]);
+        assert_eq!(queue.pending.iter().find(|order| order.id == OrderId(0)).unwrap().assigned, None);
+        assert_eq!(queue.created, 2);
+    }
+
+    #[test]
+    fn a_reload_skips_the_scheduled_orders_already_past() {
+        let schedule = |times: &[f32]| OrderSource::Schedule(times.iter()
+            .map(|&time| OrderDef { time, pickup: 0, dropoff: 0, priority: 0 })
+            .collect());
+        let mut queue = OrderQueue::default();
+
+        // The first schedule starts from the beginning of the run, whenever it is picked up
+        queue.restart(&schedule(&[1.0, 5.0, 10.0]), 2.0);
+        let times = |queue: &OrderQueue| queue.schedule.iter().rev().map(|order| order.time).collect::<Vec<_>>();
+        assert_eq!(times(&queue), vec![1.0, 5.0, 10.0]);
+
+        // A reloaded one only from now on
+        queue.restart(&schedule(&[1.0, 5.0, 10.0, 20.0]), 6.0);
+        assert_eq!(times(&queue), vec![10.0, 20.0]);
+    }
+}
@@ -0,0 +1,125 @@
+use bevy::ecs::schedule::{InternedScheduleLabel, ScheduleLabel};
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+
+use crate::collisions::{detect_collisions, CollisionDetected, CollisionLog};
+use crate::dispatch::dispatch_system;
+use crate::events::RobotStateChanged;
+use crate::metrics::{metrics_system, Metrics};
+use crate::navigation::{plan_cooperative_paths, plan_paths, rebuild_nav_grid, NavGrid, ReservationTable};
+use crate::orders::{generate_orders, OrderCompleted, OrderQueue};
+use crate::resources::{SimRng, SimulationConfig};
+use crate::spatial::{rebuild_spatial_grid, SpatialGrid};
+use crate::systems::*;
+use crate::watchdog::{watchdog_system, StallDetected};
+
+/// The groups the simulation systems run in, in this order, once per tick.
+/// Use `app.configure_sets(schedule, SwarmSimSet::Battery.run_if(...))` to gate or order around them.
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SwarmSimSet {
+    Movement,
+    StateMachine,
+    Battery,
+    Metrics,
+}
+
+/// Adds the warehouse simulation (stations, robots and their systems) to an `App`.
+///
+/// The plugin does not pick a windowing mode: add `DefaultPlugins` or `MinimalPlugins` yourself.
+pub struct SwarmSimPlugin {
+    pub config: SimulationConfig,
+    pub schedule: InternedScheduleLabel, // where the per-tick systems run (FixedUpdate by default)
+    pub tick_rate_hz: f64,
+    pub spawn_camera: bool,
+    pub seed: u64,
+    pub deterministic: bool, // advance exactly one fixed tick per app update instead of following the wall clock
+}
+
+impl SwarmSimPlugin {
+    pub fn new(config: SimulationConfig) -> Self {
+        Self {
+            config,
+            schedule: FixedUpdate.intern(),
+            tick_rate_hz: 60.0,
+            spawn_camera: true,
+            seed: 0,
+            deterministic: false,
+        }
+    }
+
+    pub fn in_schedule(mut self, schedule: impl ScheduleLabel) -> Self {
+        self.schedule = schedule.intern();
+        self
+    }
+
+    pub fn with_tick_rate(mut self, hz: f64) -> Self {
+        self.tick_rate_hz = hz;
+        self
+    }
+
+    pub fn with_camera(mut self, spawn_camera: bool) -> Self {
+        self.spawn_camera = spawn_camera;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+}
+
+impl Plugin for SwarmSimPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.config.clone())
+            .insert_resource(SimRng::from_seed(self.seed))
+            .init_resource::<Metrics>()
+            .init_resource::<SpatialGrid>()
+            .init_resource::<NavGrid>()
+            .init_resource::<ReservationTable>()
+            .init_resource::<CollisionLog>()
+            .init_resource::<OrderQueue>()
+            .add_message::<RobotStateChanged>()
+            .add_message::<StallDetected>()
+            .add_message::<CollisionDetected>()
+            .add_message::<OrderCompleted>()
+            .insert_resource(Time::<Fixed>::from_hz(self.tick_rate_hz))
+            .configure_sets(self.schedule, (
+                SwarmSimSet::Movement,
+                SwarmSimSet::StateMachine,
+                SwarmSimSet::Battery,
+                SwarmSimSet::Metrics,
+            ).chain())
+            .add_systems(Startup, setup_simulation)
+            .add_systems(self.schedule, (
+                (
+                    rebuild_spatial_grid,
+                    rebuild_nav_grid.run_if(resource_changed::<SimulationConfig>),
+                    plan_paths,
+                    plan_cooperative_paths,
+                    movement_system,
+                    detect_collisions,
+                    watchdog_system,
+                ).chain().in_set(SwarmSimSet::Movement),
+                (generate_orders, robot_state_machine, dispatch_system).chain().in_set(SwarmSimSet::StateMachine),
+                battery_system.in_set(SwarmSimSet::Battery),
+                metrics_system.in_set(SwarmSimSet::Metrics),
+            ));
+
+        // Tick lengths no longer depend on how long a frame took, so the same seed replays the same run.
+        // Otherwise simulated time follows the wall clock, sped up by the scenario's time scale
+        if self.deterministic {
+            app.insert_resource(TimeUpdateStrategy::FixedTimesteps(1));
+        } else {
+            app.add_systems(Update, apply_time_scale.run_if(resource_changed::<SimulationConfig>));
+        }
+
+        if self.spawn_camera {
+            app.add_systems(Startup, spawn_camera);
+        }
+    }
+}
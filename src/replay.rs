@@ -0,0 +1,603 @@
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use ron::error::SpannedError;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::components::*;
+use crate::resources::SimulationConfig;
+use crate::systems::{robot_color, spawn_camera, spawn_obstacles, spawn_robot, spawn_stations};
+use crate::utilityfunctions::state_checksum;
+
+// --- FILE FORMAT ---
+//
+// All numbers are little-endian.
+//
+// Header:  magic "SWARMREC", version u16, seed u64, tick rate f64 (Hz), config length u32, config (RON text)
+// Records: tag u8, then
+//   FRAME_TAG:  sim time f32, robot count u32, per robot: id u32, x f32, y f32, state u8, battery f32
+//   CONFIG_TAG: config length u32, config (RON text), applies from the next frame on
+
+const MAGIC: &[u8; 8] = b"SWARMREC";
+pub const FORMAT_VERSION: u16 = 1;
+
+const FRAME_TAG: u8 = 0;
+const CONFIG_TAG: u8 = 1;
+
+// --- ERRORS ---
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io { path: PathBuf, source: io::Error },
+    NotARecording { path: PathBuf },
+    UnsupportedVersion { path: PathBuf, version: u16 },
+    Corrupt { path: PathBuf, offset: usize, message: String },
+    Config { path: PathBuf, source: Box<SpannedError> },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io { path, source } => {
+                write!(f, "failed to read recording '{}': {source}", path.display())
+            }
+            ReplayError::NotARecording { path } => {
+                write!(f, "'{}' is not a simulation recording", path.display())
+            }
+            ReplayError::UnsupportedVersion { path, version } => {
+                write!(f, "recording '{}' has format version {version}, this build reads version {FORMAT_VERSION}", path.display())
+            }
+            ReplayError::Corrupt { path, offset, message } => {
+                write!(f, "recording '{}' is corrupt at byte {offset}: {message}", path.display())
+            }
+            ReplayError::Config { path, source } => {
+                write!(f, "recording '{}' holds an unreadable config at {}: {}", path.display(), source.span.start, source.code)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ReplayError::Io { source, .. } => Some(source),
+            ReplayError::Config { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+// --- RECORDING ---
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RobotFrame {
+    pub id: RobotId,
+    pub position: Vec2,
+    pub state: RobotState,
+    pub battery: f32,
+}
+
+/// Every robot at the end of one simulation tick, sorted by id.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub time: f32,
+    pub robots: Vec<RobotFrame>,
+}
+
+/// A recording loaded into memory.
+#[derive(Debug, Clone)]
+pub struct Recording {
+    pub seed: u64,
+    pub tick_rate_hz: f64,
+    pub configs: Vec<(usize, SimulationConfig)>, // (first frame index, config), the initial config first
+    pub frames: Vec<Frame>,
+    pub truncated: bool, // the file ended in the middle of a record, e.g. because the run crashed
+}
+
+impl Recording {
+    pub fn load(path: &Path) -> Result<Self, ReplayError> {
+        let bytes = std::fs::read(path)
+            .map_err(|source| ReplayError::Io { path: path.to_path_buf(), source })?;
+        Self::parse(path, &bytes)
+    }
+
+    pub fn parse(path: &Path, bytes: &[u8]) -> Result<Self, ReplayError> {
+        let mut reader = ByteReader { bytes, offset: 0 };
+        let corrupt = |offset: usize, message: &str| ReplayError::Corrupt {
+            path: path.to_path_buf(),
+            offset,
+            message: message.to_string(),
+        };
+
+        if reader.take(MAGIC.len()) != Some(MAGIC.as_slice()) {
+            return Err(ReplayError::NotARecording { path: path.to_path_buf() });
+        }
+        let version = reader.u16().ok_or_else(|| corrupt(reader.offset, "header is truncated"))?;
+        if version != FORMAT_VERSION {
+            return Err(ReplayError::UnsupportedVersion { path: path.to_path_buf(), version });
+        }
+        let seed = reader.u64().ok_or_else(|| corrupt(reader.offset, "header is truncated"))?;
+        let tick_rate_hz = reader.f64().ok_or_else(|| corrupt(reader.offset, "header is truncated"))?;
+        let config = reader.config(path)?.ok_or_else(|| corrupt(reader.offset, "header is truncated"))?;
+
+        let mut recording = Recording { seed, tick_rate_hz, configs: vec![(0, config)], frames: Vec::new(), truncated: false };
+
+        // A record cut short is the tail of an interrupted run: keep everything before it
+        while reader.offset < bytes.len() {
+            let start = reader.offset;
+            let complete = match reader.u8() {
+                Some(FRAME_TAG) => match reader.frame() {
+                    Ok(Some(frame)) => { recording.frames.push(frame); true }
+                    Ok(None) => false,
+                    Err(message) => return Err(corrupt(start, &message)),
+                },
+                Some(CONFIG_TAG) => match reader.config(path)? {
+                    Some(config) => { recording.configs.push((recording.frames.len(), config)); true }
+                    None => false,
+                },
+                Some(tag) => return Err(corrupt(start, &format!("unknown record tag {tag}"))),
+                None => false,
+            };
+            if !complete {
+                recording.truncated = true;
+                break;
+            }
+        }
+        Ok(recording)
+    }
+
+    /// Index of the first frame at or after `time`, clamped to the last frame.
+    pub fn frame_at(&self, time: f32) -> usize {
+        let index = self.frames.partition_point(|frame| frame.time < time);
+        index.min(self.frames.len().saturating_sub(1))
+    }
+
+    /// Index into `configs` of the config in force at frame `index`.
+    pub fn config_at(&self, index: usize) -> usize {
+        self.configs.partition_point(|(first_frame, _)| *first_frame <= index).saturating_sub(1)
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.frames.last().map_or(0.0, |frame| frame.time)
+    }
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let slice = self.bytes.get(self.offset..self.offset.checked_add(len)?)?;
+        self.offset += len;
+        Some(slice)
+    }
+
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        self.take(N).map(|slice| slice.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Option<u8> { self.array::<1>().map(|[byte]| byte) }
+    fn u16(&mut self) -> Option<u16> { self.array().map(u16::from_le_bytes) }
+    fn u32(&mut self) -> Option<u32> { self.array().map(u32::from_le_bytes) }
+    fn u64(&mut self) -> Option<u64> { self.array().map(u64::from_le_bytes) }
+    fn f32(&mut self) -> Option<f32> { self.array().map(f32::from_le_bytes) }
+    fn f64(&mut self) -> Option<f64> { self.array().map(f64::from_le_bytes) }
+
+    // Ok(None) means the data ran out
+    fn config(&mut self, path: &Path) -> Result<Option<SimulationConfig>, ReplayError> {
+        let start = self.offset;
+        let Some(text) = self.u32().and_then(|len| self.take(len as usize)) else { return Ok(None) };
+        let text = std::str::from_utf8(text).map_err(|_| ReplayError::Corrupt {
+            path: path.to_path_buf(),
+            offset: start,
+            message: "config is not valid UTF-8".into(),
+        })?;
+        ron::from_str(text)
+            .map(Some)
+            .map_err(|source| ReplayError::Config { path: path.to_path_buf(), source: Box::new(source) })
+    }
+
+    fn frame(&mut self) -> Result<Option<Frame>, String> {
+        let (Some(time), Some(count)) = (self.f32(), self.u32()) else { return Ok(None) };
+        let mut robots = Vec::with_capacity((count as usize).min(self.bytes.len() / 17));
+        for _ in 0..count {
+            let (Some(id), Some(x), Some(y), Some(state), Some(battery)) =
+                (self.u32(), self.f32(), self.f32(), self.u8(), self.f32()) else { return Ok(None) };
+            let state = *RobotState::ALL.get(state as usize)
+                .ok_or_else(|| format!("robot {id} has unknown state {state}"))?;
+            robots.push(RobotFrame { id: RobotId(id), position: Vec2::new(x, y), state, battery });
+        }
+        Ok(Some(Frame { time, robots }))
+    }
+}
+
+// --- RECORDER ---
+
+/// Appends one frame per simulation tick to a recording file.
+/// The header is written on the first tick, once the tick rate and config are known.
+#[derive(Resource)]
+pub struct Recorder {
+    seed: u64,
+    writer: BufWriter<File>,
+    header_written: bool,
+    failed: bool,
+}
+
+impl Recorder {
+    /// Creates (or truncates) the recording file.
+    pub fn create(path: &Path, seed: u64) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        Ok(Self { seed, writer: BufWriter::new(File::create(path)?), header_written: false, failed: false })
+    }
+
+    fn write_header(&mut self, tick_rate_hz: f64, config: &SimulationConfig) -> io::Result<()> {
+        self.writer.write_all(MAGIC)?;
+        self.writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        self.writer.write_all(&self.seed.to_le_bytes())?;
+        self.writer.write_all(&tick_rate_hz.to_le_bytes())?;
+        self.write_config(config)
+    }
+
+    fn write_config(&mut self, config: &SimulationConfig) -> io::Result<()> {
+        let text = ron::to_string(config).map_err(io::Error::other)?;
+        self.writer.write_all(&(text.len() as u32).to_le_bytes())?;
+        self.writer.write_all(text.as_bytes())
+    }
+
+    fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        self.writer.write_all(&[FRAME_TAG])?;
+        self.writer.write_all(&frame.time.to_le_bytes())?;
+        self.writer.write_all(&(frame.robots.len() as u32).to_le_bytes())?;
+        for robot in &frame.robots {
+            self.writer.write_all(&robot.id.0.to_le_bytes())?;
+            self.writer.write_all(&robot.position.x.to_le_bytes())?;
+            self.writer.write_all(&robot.position.y.to_le_bytes())?;
+            self.writer.write_all(&[robot.state.index() as u8])?;
+            self.writer.write_all(&robot.battery.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    // Reports the first write error and stops recording, rather than failing every tick
+    fn check(&mut self, result: io::Result<()>) {
+        if let Err(err) = result {
+            eprintln!("error: recording stopped: {err}");
+            self.failed = true;
+        }
+    }
+}
+
+/// Adds the recording systems. Does nothing unless a `Recorder` resource is inserted.
+pub struct RecordPlugin;
+
+impl Plugin for RecordPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(FixedUpdate, record_frame
+                .after(crate::SwarmSimSet::Metrics)
+                .run_if(resource_exists::<Recorder>))
+            .add_systems(Last, flush_recording
+                .run_if(resource_exists::<Recorder>.and(on_message::<AppExit>)));
+    }
+}
+
+pub fn record_frame(
+    time: Res<Time<Fixed>>,
+    config: Res<SimulationConfig>,
+    mut recorder: ResMut<Recorder>,
+    robot_query: Query<(&RobotId, &Transform, &RobotState, &Battery), With<Robot>>
+)
+{
+    if recorder.failed { return; }
+
+    let mut result = Ok(());
+    if !recorder.header_written {
+        recorder.header_written = true;
+        result = recorder.write_header(1.0 / time.timestep().as_secs_f64(), &config);
+    } else if config.is_changed() {
+        // A hot reload: later frames are replayed against the new stations
+        result = recorder.writer.write_all(&[CONFIG_TAG]).and_then(|_| recorder.write_config(&config));
+    }
+
+    let mut robots: Vec<RobotFrame> = robot_query.iter().map(|(id, transform, state, battery)| RobotFrame {
+        id: *id,
+        position: transform.translation.truncate(),
+        state: *state,
+        battery: battery.0,
+    }).collect();
+    robots.sort_unstable_by_key(|robot| robot.id);
+
+    let frame = Frame { time: time.elapsed_secs(), robots };
+    let result = result.and_then(|_| recorder.write_frame(&frame));
+    recorder.check(result);
+}
+
+pub fn flush_recording(mut recorder: ResMut<Recorder>) {
+    let result = recorder.flush();
+    recorder.check(result);
+}
+
+// --- REPLAY ---
+
+/// Playback position in a loaded recording.
+#[derive(Resource)]
+pub struct Replay {
+    pub recording: Recording,
+    pub cursor: usize, // index of the frame on screen
+    pub stop: usize,   // last frame to play
+    pub paused: bool,
+    shown_frame: Option<usize>,
+    shown_config: Option<usize>,
+}
+
+impl Replay {
+    /// Starts playback at `start` and ends it at `stop` (simulated seconds), defaulting to the whole recording.
+    pub fn new(recording: Recording, start: Option<f32>, stop: Option<f32>) -> Self {
+        let cursor = start.map_or(0, |time| recording.frame_at(time));
+        let stop = stop.map_or(recording.frames.len().saturating_sub(1), |time| recording.frame_at(time));
+        Self { recording, cursor, stop: stop.max(cursor), paused: false, shown_frame: None, shown_config: None }
+    }
+
+    pub fn seek(&mut self, time: f32) {
+        self.cursor = self.recording.frame_at(time).min(self.stop);
+    }
+
+    pub fn frame(&self) -> Option<&Frame> {
+        self.recording.frames.get(self.cursor)
+    }
+
+    pub fn finished(&self) -> bool {
+        self.cursor >= self.stop
+    }
+}
+
+/// Plays back a `Replay` resource: positions, states and battery levels come straight
+/// from the recording, none of the simulation's decision systems run.
+pub struct ReplayPlugin {
+    pub tick_rate_hz: f64,
+    pub spawn_camera: bool,
+    pub exit_at_end: bool, // headless replays print the final frame and exit
+    pub fast_forward: bool, // one frame per app update instead of real time
+}
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Time::<Fixed>::from_hz(self.tick_rate_hz))
+            .add_systems(FixedUpdate, (step_replay, apply_replay_frame).chain())
+            .add_systems(Update, replay_controls.run_if(resource_exists::<ButtonInput<KeyCode>>));
+
+        if self.fast_forward {
+            app.insert_resource(TimeUpdateStrategy::FixedTimesteps(1));
+        }
+        if self.spawn_camera {
+            app.add_systems(Startup, spawn_camera);
+        }
+        if self.exit_at_end {
+            app.add_systems(FixedUpdate, finish_replay.after(apply_replay_frame));
+        }
+    }
+}
+
+// --- REPLAY SYSTEMS ---
+
+pub fn step_replay(mut replay: ResMut<Replay>) {
+    // Show the starting frame for one tick before moving on
+    if replay.paused || replay.shown_frame.is_none() || replay.finished() { return; }
+    replay.cursor += 1;
+}
+
+// What a config spawns besides the robots
+type MapEntity = Or<(With<StationId>, With<Obstacle>)>;
+
+type ReplayedRobot = (Entity, &'static RobotId, &'static mut Transform, &'static mut RobotState, &'static mut Battery, &'static mut Sprite);
+
+pub fn apply_replay_frame(
+    mut commands: Commands,
+    mut replay: ResMut<Replay>,
+    map_query: Query<Entity, MapEntity>,
+    mut robot_query: Query<ReplayedRobot, With<Robot>>
+)
+{
+    if replay.shown_frame == Some(replay.cursor) { return; }
+    let Some(frame) = replay.frame() else { return };
+
+    // Stations and obstacles only change with the config, so rebuild them when a different config is in force
+    let config_index = replay.recording.config_at(replay.cursor);
+    let config = &replay.recording.configs[config_index].1;
+    if replay.shown_config != Some(config_index) {
+        for entity in &map_query {
+            commands.entity(entity).despawn();
+        }
+        spawn_obstacles(&mut commands, config);
+        spawn_stations(&mut commands, config);
+    }
+
+    let mut seen: Vec<RobotId> = Vec::with_capacity(frame.robots.len());
+    for (entity, id, mut transform, mut state, mut battery, mut sprite) in &mut robot_query {
+        match frame.robots.binary_search_by_key(id, |robot| robot.id) {
+            Ok(index) => {
+                let robot = &frame.robots[index];
+                transform.translation = robot.position.extend(transform.translation.z);
+                *state = robot.state;
+                battery.0 = robot.battery;
+                sprite.color = robot_color(robot.state, robot.battery);
+                seen.push(*id);
+            }
+            Err(_) => commands.entity(entity).despawn(),
+        }
+    }
+
+    // Robots added by a hot reload, or every robot on the first frame
+    for robot in frame.robots.iter().filter(|robot| !seen.contains(&robot.id)) {
+        let entity = spawn_robot(&mut commands, config, robot.id);
+        commands.entity(entity).insert((
+            Transform::from_translation(robot.position.extend(0.0)),
+            robot.state,
+            Battery(robot.battery),
+            Sprite::from_color(robot_color(robot.state, robot.battery), Vec2::splat(ROBOT_SIZE)),
+        ));
+    }
+
+    replay.shown_config = Some(config_index);
+    replay.shown_frame = Some(replay.cursor);
+}
+
+/// Space pauses, Left/Right seek 10 seconds, Home jumps back to the start.
+pub fn replay_controls(keys: Res<ButtonInput<KeyCode>>, mut replay: ResMut<Replay>) {
+    const SEEK_STEP: f32 = 10.0;
+    let Some(now) = replay.frame().map(|frame| frame.time) else { return };
+
+    if keys.just_pressed(KeyCode::Space) {
+        replay.paused = !replay.paused;
+        println!("{} Replay at {:.1}s", if replay.paused { "⏸️" } else { "▶️" }, now);
+    }
+    let target = if keys.just_pressed(KeyCode::ArrowRight) {
+        Some(now + SEEK_STEP)
+    } else if keys.just_pressed(KeyCode::ArrowLeft) {
+        Some(now - SEEK_STEP)
+    } else if keys.just_pressed(KeyCode::Home) {
+        Some(0.0)
+    } else {
+        None
+    };
+    if let Some(target) = target {
+        replay.seek(target);
+        println!("⏩ Seeked to {:.1}s", replay.frame().map_or(0.0, |frame| frame.time));
+    }
+}
+
+pub fn finish_replay(replay: Res<Replay>, mut exit: MessageWriter<AppExit>, mut finished: Local<bool>) {
+    if *finished || !replay.finished() || replay.shown_frame != Some(replay.cursor) { return; }
+    *finished = true;
+
+    if let Some(frame) = replay.frame() {
+        let checksum = state_checksum(frame.robots.iter()
+            .map(|robot| (robot.id.0, robot.position.extend(0.0), robot.state as u8)));
+        println!("⏪ Replay finished at {:.1}s simulated", frame.time);
+        println!("   Final state checksum: {checksum:016x}");
+        for robot in &frame.robots {
+            println!("   Robot {:>3}: ({:>8.1}, {:>8.1}) {:?}, battery {:.1}%",
+                robot.id.0, robot.position.x, robot.position.y, robot.state, robot.battery);
+        }
+    }
+    exit.write(AppExit::Success);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(robot_count: usize) -> SimulationConfig {
+        SimulationConfig { robot_count, ..SimulationConfig::for_tests() }
+    }
+
+    fn frame(time: f32, states: &[RobotState]) -> Frame {
+        let robots = states.iter().enumerate().map(|(i, &state)| RobotFrame {
+            id: RobotId(i as u32),
+            position: Vec2::new(i as f32 * 10.0, time),
+            state,
+            battery: 100.0 - time,
+        }).collect();
+        Frame { time, robots }
+    }
+
+    // What the Recorder writes for these frames, with a config change before frame `reload_at`
+    fn record(name: &str, frames: &[Frame], reload_at: Option<(usize, &SimulationConfig)>) -> Vec<u8> {
+        let path = std::env::temp_dir().join(format!("swarm_replay_{name}_{}.rec", std::process::id()));
+        let mut recorder = Recorder::create(&path, 42).unwrap();
+        recorder.write_header(60.0, &config(2)).unwrap();
+        for (index, frame) in frames.iter().enumerate() {
+            if let Some((_, config)) = reload_at.filter(|(at, _)| *at == index) {
+                recorder.writer.write_all(&[CONFIG_TAG]).unwrap();
+                recorder.write_config(config).unwrap();
+            }
+            recorder.write_frame(frame).unwrap();
+        }
+        recorder.flush().unwrap();
+        drop(recorder);
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        bytes
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Recording, ReplayError> {
+        Recording::parse(Path::new("test.rec"), bytes)
+    }
+
+    #[test]
+    fn round_trip() {
+        let frames = vec![
+            frame(0.0, &[RobotState::Idle, RobotState::Idle]),
+            frame(1.0, &[RobotState::MovingToPickup, RobotState::Charging]),
+            frame(2.0, &[RobotState::PickingUp, RobotState::Dead, RobotState::Idle]),
+        ];
+        let reloaded = config(3);
+        let recording = parse(&record("round_trip", &frames, Some((2, &reloaded)))).unwrap();
+
+        assert_eq!(recording.seed, 42);
+        assert_eq!(recording.tick_rate_hz, 60.0);
+        assert_eq!(recording.frames, frames);
+        assert!(!recording.truncated);
+
+        let configs: Vec<(usize, String)> = recording.configs.iter()
+            .map(|(first_frame, config)| (*first_frame, ron::to_string(config).unwrap()))
+            .collect();
+        assert_eq!(configs, vec![
+            (0, ron::to_string(&config(2)).unwrap()),
+            (2, ron::to_string(&reloaded).unwrap()),
+        ]);
+        assert_eq!(recording.config_at(1), 0);
+        assert_eq!(recording.config_at(2), 1);
+    }
+
+    #[test]
+    fn keeps_the_frames_before_a_truncated_tail() {
+        let frames = vec![frame(0.0, &[RobotState::Idle]), frame(1.0, &[RobotState::MovingToPickup])];
+        let bytes = record("truncated", &frames, None);
+
+        let recording = parse(&bytes[..bytes.len() - 3]).unwrap();
+        assert!(recording.truncated);
+        assert_eq!(recording.frames, frames[..1]);
+    }
+
+    #[test]
+    fn rejects_a_file_without_the_magic() {
+        let mut bytes = record("magic", &[], None);
+        bytes[..8].copy_from_slice(b"NOTSWARM");
+        assert!(matches!(parse(&bytes), Err(ReplayError::NotARecording { .. })));
+    }
+
+    #[test]
+    fn rejects_another_format_version() {
+        let mut bytes = record("version", &[], None);
+        bytes[8..10].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert!(matches!(parse(&bytes), Err(ReplayError::UnsupportedVersion { version, .. }) if version == FORMAT_VERSION + 1));
+    }
+
+    #[test]
+    fn rejects_an_unknown_state() {
+        let mut bytes = record("state", &[frame(0.0, &[RobotState::Idle])], None);
+        // The frame's only robot ends with its state byte and a 4-byte battery level
+        let state_byte = bytes.len() - 5;
+        bytes[state_byte] = RobotState::ALL.len() as u8;
+        let frame_start = bytes.len() - (1 + 4 + 4 + 4 + 4 + 4 + 1 + 4);
+
+        match parse(&bytes) {
+            Err(ReplayError::Corrupt { offset, message, .. }) => {
+                assert_eq!(offset, frame_start);
+                assert!(message.contains("unknown state"), "{message}");
+            }
+            other => panic!("expected a corrupt recording, got {other:?}"),
+        }
+    }
+}
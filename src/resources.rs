@@ -1,21 +1,322 @@
 use bevy::prelude::*;
-use serde::Deserialize;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 
-#[derive(Resource, Deserialize, Debug, Clone)]
+use crate::components::{ObstacleShape, ServiceTime, StationKind};
+
+#[derive(Resource, Deserialize, Serialize, Debug, Clone)]
 pub struct SimulationConfig {
     pub robot_count: usize,
     pub robot_speed: f32,
+    #[serde(default = "default_max_acceleration")]
+    pub max_acceleration: f32, // units per second squared, speeding up and braking alike
+    #[serde(default = "default_max_angular_rate")]
+    pub max_angular_rate: f32, // radians per second
     pub collision_radius: f32,
     pub state_change_radius: f32,
     
     // battery params
     pub low_battery_threshold: f32,
     pub dead_battery_threshold: f32,
-    pub drain_idle: f32,
-    pub drain_move: f32,
-    pub charging_time: f32,
-
-    pub pickup_stations: Vec<(f32, f32)>,
-    pub dropoff_stations: Vec<(f32, f32)>,
-    pub charger_stations: Vec<(f32, f32)>,
-}
\ No newline at end of file
+    pub idle_power_w: f32, // watts a robot draws standing still
+    pub move_power_w: f32, // watts a robot draws driving
+    #[serde(default)]
+    pub battery: BatteryConfig, // capacity and charging policy
+
+    pub pickup_stations: Vec<StationDef>,
+    pub dropoff_stations: Vec<StationDef>,
+    pub charger_stations: Vec<StationDef>,
+
+    #[serde(default)]
+    pub obstacles: Vec<ObstacleDef>, // racks and walls, none by default
+
+    #[serde(default)]
+    pub navigation: NavigationMode,
+
+    #[serde(default)]
+    pub avoidance: AvoidanceMode,
+
+    #[serde(default)]
+    pub watchdog: WatchdogConfig,
+
+    #[serde(default)]
+    pub orders: OrderSource,
+
+    #[serde(default)]
+    pub dispatch: DispatchMode,
+
+    #[serde(default = "default_time_scale")]
+    pub time_scale: f32, // simulated seconds per real second; deterministic runs take one tick per frame instead
+}
+
+fn default_time_scale() -> f32 {
+    1.0
+}
+
+fn default_max_acceleration() -> f32 {
+    600.0
+}
+
+fn default_max_angular_rate() -> f32 {
+    4.0 * std::f32::consts::PI
+}
+
+/// How robots find their way to a target.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NavigationMode {
+    #[default]
+    Decentralized, // each robot plans alone and steers around the others as it meets them
+    Cooperative,   // robots plan in turn around each other's reservations in space and time, and don't steer
+}
+
+/// How robots keep clear of each other while following their paths. Cooperative navigation uses neither.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AvoidanceMode {
+    #[default]
+    Separation, // neighbours push each other apart, the lower entity giving way
+    Orca,       // each robot picks the velocity closest to its preferred one that can't collide within a time horizon
+}
+
+/// How free stations and orders are shared out among the robots waiting for one.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DispatchMode {
+    #[default]
+    FirstFree,   // robots in id order take the free station with the lowest id (or the order that goes first)
+    NearestFree, // robots in id order take the closest free one
+    Hungarian,   // the assignment with the least total distance, solved every tick
+    Auction,     // robots bid up the price of the free one worth most to them, outbid robots bid again (ε-auction)
+}
+
+/// Battery size, how robots charge and how batteries wear. Chargers set their own power, see `StationDef::power`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct BatteryConfig {
+    pub capacity_wh: f32,  // energy a full battery holds
+    pub cv_from: f32,      // charge (%) past which charging switches from constant current to constant voltage and tapers off
+    pub charge_until: f32, // charge (%) a robot charges up to before it leaves the charger
+
+    // wear, see BatteryHealth
+    pub cycle_fade: f32,            // health (%) a full 100% discharge costs
+    pub dod_exponent: f32,          // above 1, deep discharges wear a battery more than the same energy in shallow ones
+    pub calendar_fade_per_day: f32, // health (%) lost per simulated day, used or not
+    pub replace_below: Option<f32>, // health (%) under which a battery is swapped for a new, full one at its next charge
+}
+
+impl Default for BatteryConfig {
+    fn default() -> Self {
+        Self {
+            capacity_wh: 1000.0,
+            cv_from: 80.0,
+            charge_until: 90.0,
+            cycle_fade: 0.02,
+            dod_exponent: 1.5,
+            calendar_fade_per_day: 0.0,
+            replace_below: None,
+        }
+    }
+}
+
+/// When a moving robot counts as stuck, and how long stuck robots back away.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct WatchdogConfig {
+    pub stall_window: f32, // seconds a moving robot may go without getting closer to its target
+    pub backoff_time: f32, // seconds a stuck robot backs away before heading for its target again
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self { stall_window: 5.0, backoff_time: 1.0 }
+    }
+}
+
+/// Where delivery orders come from.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
+pub enum OrderSource {
+    #[default]
+    Unlimited,                 // no orders: idle robots take any free pickup, then any free dropoff
+    Poisson { per_hour: f32 }, // orders between random stations, arriving at this average rate
+    Schedule(Vec<OrderDef>),   // orders arriving at fixed times
+    Csv(PathBuf),              // a schedule in a CSV file (time,pickup,dropoff[,priority]), relative to the scenario
+}
+
+/// One order of a schedule. Stations are indexes into `pickup_stations` and `dropoff_stations`.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct OrderDef {
+    pub time: f32, // simulated seconds
+    pub pickup: usize,
+    pub dropoff: usize,
+    #[serde(default)]
+    pub priority: u8, // higher goes first
+}
+
+/// A station as written in the scenario file: just its position, `(x, y)`,
+/// or its position and options, `(position: (x, y), bays: [(-30.0, 0.0), (30.0, 0.0)], queue: [(0.0, -60.0)], service: Uniform(min: 2.0, max: 5.0))`,
+/// or for a charger `(position: (x, y), power: 900.0)`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(from = "StationEntry", into = "StationEntry")]
+pub struct StationDef {
+    pub position: (f32, f32),
+    pub bays: Vec<(f32, f32)>,  // where robots dock, relative to the station, one robot per bay. None: a single bay on the station
+    pub queue: Vec<(f32, f32)>, // spots robots wait at when the station is taken, relative to it, front of the line first
+    pub service: ServiceTime,   // time to pick up or drop off a load, pickups and dropoffs only
+    pub power: f32,             // watts each bay charges with, chargers only
+}
+
+pub const DEFAULT_CHARGER_POWER: f32 = 1_800.0; // watts: a common warehouse robot charger, a default battery charges to 80% in under half an hour
+
+fn default_charger_power() -> f32 {
+    DEFAULT_CHARGER_POWER
+}
+
+impl StationDef {
+    /// Where robots dock, in world coordinates.
+    pub fn docks(&self) -> Vec<Vec2> {
+        if self.bays.is_empty() {
+            return vec![Vec2::from(self.position)];
+        }
+        self.bays.iter().map(|&offset| Vec2::from(self.position) + Vec2::from(offset)).collect()
+    }
+
+    /// The queue spots in world coordinates.
+    pub fn queue_spots(&self) -> impl Iterator<Item = Vec2> + '_ {
+        self.queue.iter().map(|&offset| Vec2::from(self.position) + Vec2::from(offset))
+    }
+}
+
+// The two ways of writing a station. Plain positions are written back as plain positions.
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(untagged, expecting = "expected a station, (x, y) or (position: (x, y), bays: [...], queue: [...], service: ..., power: ...)")]
+enum StationEntry {
+    At((f32, f32)),
+    With {
+        position: (f32, f32),
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        bays: Vec<(f32, f32)>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        queue: Vec<(f32, f32)>,
+        #[serde(default)]
+        service: ServiceTime,
+        #[serde(default = "default_charger_power")]
+        power: f32,
+    },
+}
+
+impl From<StationEntry> for StationDef {
+    fn from(entry: StationEntry) -> Self {
+        match entry {
+            StationEntry::At(position) => StationDef {
+                position,
+                bays: Vec::new(),
+                queue: Vec::new(),
+                service: ServiceTime::default(),
+                power: DEFAULT_CHARGER_POWER,
+            },
+            StationEntry::With { position, bays, queue, service, power } => StationDef { position, bays, queue, service, power },
+        }
+    }
+}
+
+impl From<StationDef> for StationEntry {
+    fn from(station: StationDef) -> Self {
+        if station.bays.is_empty() && station.queue.is_empty() && station.service == ServiceTime::default() && station.power == DEFAULT_CHARGER_POWER {
+            StationEntry::At(station.position)
+        } else {
+            StationEntry::With {
+                position: station.position,
+                bays: station.bays,
+                queue: station.queue,
+                service: station.service,
+                power: station.power,
+            }
+        }
+    }
+}
+
+/// An obstacle as written in the scenario file.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum ObstacleDef {
+    Rect { min: (f32, f32), max: (f32, f32) }, // e.g. a shelving rack
+    Polyline { points: Vec<(f32, f32)>, thickness: f32 }, // e.g. a wall, one segment between each pair of consecutive points
+}
+
+impl ObstacleDef {
+    pub fn shapes(&self) -> Vec<ObstacleShape> {
+        match self {
+            ObstacleDef::Rect { min, max } => {
+                let (min, max) = (Vec2::from(*min), Vec2::from(*max));
+                vec![ObstacleShape::Box { center: (min + max) / 2.0, half_size: (max - min) / 2.0 }]
+            }
+            ObstacleDef::Polyline { points, thickness } => points.windows(2)
+                .map(|pair| ObstacleShape::Segment {
+                    start: Vec2::from(pair[0]),
+                    end: Vec2::from(pair[1]),
+                    half_thickness: thickness / 2.0,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl SimulationConfig {
+    pub fn stations(&self, kind: StationKind) -> &[StationDef] {
+        match kind {
+            StationKind::Pickup => &self.pickup_stations,
+            StationKind::Dropoff => &self.dropoff_stations,
+            StationKind::Charger => &self.charger_stations,
+        }
+    }
+}
+
+// Run parameters that come from the command line rather than the scenario file
+#[derive(Resource, Debug, Clone)]
+pub struct RunSettings {
+    pub duration: Option<f32>, // simulated seconds before exiting, None = run forever
+    pub target_deliveries: Option<u64>, // deliveries before exiting, None = no target
+    pub seed: u64,
+    pub output_dir: PathBuf,
+}
+
+// The only source of randomness in the simulation, so a seed fully determines a run
+#[derive(Resource, Debug, Clone)]
+pub struct SimRng(pub ChaCha8Rng);
+
+impl SimRng {
+    pub fn from_seed(seed: u64) -> Self {
+        Self(ChaCha8Rng::seed_from_u64(seed))
+    }
+}
+
+#[cfg(test)]
+impl SimulationConfig {
+    /// A small valid scenario for unit tests: two robots, one pickup, one dropoff and one charger, and defaults
+    /// for everything optional. Tests override the fields they care about.
+    pub fn for_tests() -> Self {
+        let station = |position| StationDef::from(StationEntry::At(position));
+        Self {
+            robot_count: 2,
+            robot_speed: 150.0,
+            max_acceleration: default_max_acceleration(),
+            max_angular_rate: default_max_angular_rate(),
+            collision_radius: 80.0,
+            state_change_radius: 5.0,
+            low_battery_threshold: 30.0,
+            dead_battery_threshold: 5.0,
+            idle_power_w: 20.0,
+            move_power_w: 150.0,
+            battery: BatteryConfig::default(),
+            pickup_stations: vec![station((-300.0, 0.0))],
+            dropoff_stations: vec![station((300.0, 0.0))],
+            charger_stations: vec![station((0.0, -300.0))],
+            obstacles: Vec::new(),
+            navigation: NavigationMode::default(),
+            avoidance: AvoidanceMode::default(),
+            watchdog: WatchdogConfig::default(),
+            orders: OrderSource::default(),
+            dispatch: DispatchMode::default(),
+            time_scale: default_time_scale(),
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+
+use crate::components::*;
+use crate::resources::SimulationConfig;
+use crate::watchdog::Stall;
+
+/// A robot as the others around it see it at the start of the tick.
+#[derive(Debug, Clone, Copy)]
+pub struct Neighbour {
+    pub id: RobotId,
+    pub entity: Entity,
+    pub position: Vec3,
+    pub velocity: Vec3,
+    pub priority: bool, // has right of way while recovering from a stall
+}
+
+/// Uniform grid over robot positions, rebuilt at the start of every tick.
+/// Cells are `collision_radius` wide, so everything within that radius of a point
+/// lies in the 3x3 block of cells around it.
+#[derive(Resource, Debug, Default)]
+pub struct SpatialGrid {
+    cell_size: f32,
+    cells: HashMap<IVec2, Vec<Neighbour>>,
+}
+
+impl SpatialGrid {
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    pub fn cell_of(&self, position: Vec3) -> IVec2 {
+        (position.truncate() / self.cell_size).floor().as_ivec2()
+    }
+
+    /// Empties the grid. Cells that were occupied keep their allocation for the next rebuild,
+    /// cells that were already empty are dropped so the map doesn't grow with every cell ever visited.
+    pub fn clear(&mut self, cell_size: f32) {
+        if cell_size != self.cell_size {
+            self.cells.clear();
+            self.cell_size = cell_size;
+        }
+        self.cells.retain(|_, robots| {
+            let occupied = !robots.is_empty();
+            robots.clear();
+            occupied
+        });
+    }
+
+    pub fn insert(&mut self, robot: Neighbour) {
+        let cell = self.cell_of(robot.position);
+        self.cells.entry(cell).or_default().push(robot);
+    }
+
+    /// Robots in the cells touching a circle of `radius` around `position`.
+    /// May include robots slightly outside the radius; callers filter by distance.
+    pub fn nearby(&self, position: Vec3, radius: f32) -> impl Iterator<Item = &Neighbour> {
+        let reach = (radius / self.cell_size).ceil().max(1.0) as i32;
+        let center = self.cell_of(position);
+        (-reach..=reach)
+            .flat_map(move |dy| (-reach..=reach).map(move |dx| center + IVec2::new(dx, dy)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+    }
+}
+
+// --- SYSTEM ---
+
+pub fn rebuild_spatial_grid(
+    time: Res<Time>,
+    config: Res<SimulationConfig>,
+    mut grid: ResMut<SpatialGrid>,
+    robot_query: Query<(Entity, &RobotId, &Transform, &Velocity, &Stall), With<Robot>>
+)
+{
+    grid.clear(config.collision_radius);
+    for (entity, id, transform, velocity, stall) in &robot_query {
+        grid.insert(Neighbour {
+            id: *id,
+            entity,
+            position: transform.translation,
+            velocity: velocity.0,
+            priority: stall.has_priority(time.elapsed_secs()),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    fn robot(world: &mut World, id: u32, x: f32, y: f32) -> Neighbour {
+        Neighbour { id: RobotId(id), entity: world.spawn_empty().id(), position: Vec3::new(x, y, 0.0), velocity: Vec3::ZERO, priority: false }
+    }
+
+    // The ids `nearby` finds within `radius`, after the distance check callers do
+    fn within(grid: &SpatialGrid, position: Vec3, radius: f32) -> Vec<u32> {
+        let mut ids: Vec<u32> = grid.nearby(position, radius)
+            .filter(|robot| robot.position.distance(position) <= radius)
+            .map(|robot| robot.id.0)
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn finds_exactly_the_robots_within_the_radius() {
+        let mut world = World::new();
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        // Around the origin, so cells on both sides of zero are used
+        let robots: Vec<Neighbour> = (0..400)
+            .map(|id| robot(&mut world, id, rng.random_range(-400.0..400.0), rng.random_range(-400.0..400.0)))
+            .collect();
+        let mut grid = SpatialGrid::default();
+        grid.clear(80.0);
+        for robot in &robots {
+            grid.insert(*robot);
+        }
+
+        for _ in 0..200 {
+            let position = Vec3::new(rng.random_range(-400.0..400.0), rng.random_range(-400.0..400.0), 0.0);
+            for radius in [30.0, 80.0, 200.0] {
+                let expected: Vec<u32> = robots.iter()
+                    .filter(|robot| robot.position.distance(position) <= radius)
+                    .map(|robot| robot.id.0)
+                    .collect();
+                assert_eq!(within(&grid, position, radius), expected, "around {position} within {radius}");
+            }
+        }
+    }
+
+    #[test]
+    fn sees_across_cell_borders_and_zero() {
+        let mut world = World::new();
+        let mut grid = SpatialGrid::default();
+        grid.clear(80.0);
+        // Just either side of the borders at 0 and -80
+        for (id, x, y) in [(0, -0.5, -0.5), (1, 0.5, 0.5), (2, -79.5, 0.0), (3, -80.5, 0.0), (4, 0.0, -160.0)] {
+            grid.insert(robot(&mut world, id, x, y));
+        }
+
+        assert_eq!(grid.cell_of(Vec3::new(-0.5, -0.5, 0.0)), IVec2::new(-1, -1));
+        assert_eq!(grid.cell_of(Vec3::new(0.5, 0.5, 0.0)), IVec2::new(0, 0));
+        assert_eq!(within(&grid, Vec3::ZERO, 1.0), [0, 1]);
+        assert_eq!(within(&grid, Vec3::new(-80.0, 0.0, 0.0), 1.0), [2, 3]);
+        assert_eq!(within(&grid, Vec3::new(-40.0, -40.0, 0.0), 80.0), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn clearing_keeps_occupied_cells_and_drops_empty_ones() {
+        let mut world = World::new();
+        let mut grid = SpatialGrid::default();
+        grid.clear(80.0);
+        grid.insert(robot(&mut world, 0, 10.0, 10.0));
+        grid.insert(robot(&mut world, 1, -200.0, 10.0));
+
+        // Both cells were occupied, so both are kept for the next rebuild
+        grid.clear(80.0);
+        assert_eq!(grid.cells.len(), 2);
+        assert!(grid.cells.values().all(Vec::is_empty));
+        assert_eq!(grid.nearby(Vec3::new(10.0, 10.0, 0.0), 80.0).count(), 0);
+
+        // Only the first is used again, so the second goes at the next clear
+        grid.insert(robot(&mut world, 0, 20.0, 10.0));
+        grid.clear(80.0);
+        assert_eq!(grid.cells.keys().copied().collect::<Vec<_>>(), [IVec2::new(0, 0)]);
+
+        // A new cell size starts from scratch
+        grid.clear(100.0);
+        assert!(grid.cells.is_empty());
+        assert_eq!(grid.cell_size(), 100.0);
+    }
+}
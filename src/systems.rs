@@ -1,196 +1,379 @@
 use bevy::prelude::*;
 // use bevy::input::mouse::{MouseMotion, MouseWheel};
 
+use crate::avoidance::{avoidance_model, AvoidanceInput};
 use crate::components::*;
-use crate::resources::SimulationConfig;
+use crate::events::RobotStateChanged;
+use crate::navigation::NavPath;
+use crate::orders::{AssignedOrder, OrderCompleted};
+use crate::resources::{NavigationMode, OrderSource, SimRng, SimulationConfig, StationDef};
+use crate::spatial::{Neighbour, SpatialGrid};
 use crate::utilityfunctions::*;
+use crate::watchdog::Stall;
 
 // --- SETUP ---
 
-pub fn setup_simulation(mut commands: Commands, config: Res<SimulationConfig>) 
+pub fn spawn_camera(mut commands: Commands) 
 {
     // let center_x = (config.robot_count as f32 * 100.0) / 2.0;
-    // // Camera
     commands.spawn((
         Camera2d, 
         Transform::from_xyz(-30.0, -430.0, 0.0)
     ));
+}
+
+/// Runs simulated time `time_scale` times as fast as the wall clock. More fixed ticks run per frame; each stays as long.
+pub fn apply_time_scale(config: Res<SimulationConfig>, mut time: ResMut<Time<Virtual>>)
+{
+    time.set_relative_speed(config.time_scale);
+}
+
+pub fn setup_simulation(mut commands: Commands, config: Res<SimulationConfig>) 
+{
+    spawn_obstacles(&mut commands, &config);
+    spawn_stations(&mut commands, &config);
+
+    // Robots
+    for i in 0..config.robot_count 
+    {
+        spawn_robot(&mut commands, &config, RobotId(i as u32));
+    }
+}
+
+pub fn spawn_stations(commands: &mut Commands, config: &SimulationConfig)
+{
+    // Station ids run across all kinds in config order: pickups, dropoffs, then chargers
+    let mut next_station_id = 0;
 
     // Pickups
-    for (x, y) in &config.pickup_stations 
+    for station in &config.pickup_stations 
     {
-        commands.spawn((
-            Sprite::from_color(Color::srgb(0.0, 1.0, 0.0), 
-            Vec2::new(40.0, 40.0)), 
-            Transform::from_xyz(*x, *y, 0.0), 
-            PickupStation, 
-            Booked(false)
-        ));
+        spawn_station(commands, StationKind::Pickup, StationId(next_station_id), station);
+        next_station_id += 1;
     }
     // Dropoffs
-    for (x, y) in &config.dropoff_stations 
+    for station in &config.dropoff_stations 
     {
-        commands.spawn((
-            Sprite::from_color(Color::srgb(0.0, 0.0, 1.0), 
-            Vec2::new(40.0, 40.0)), 
-            Transform::from_xyz(*x, *y, 0.0), 
-            DropoffStation, 
-            Booked(false)
-        ));
+        spawn_station(commands, StationKind::Dropoff, StationId(next_station_id), station);
+        next_station_id += 1;
     }
     // Chargers
-    for (x, y) in &config.charger_stations 
+    for station in &config.charger_stations 
     {
-        commands.spawn((
-            Sprite::from_color(Color::srgb(1.0, 1.0, 0.0), 
-            Vec2::new(50.0, 50.0)), 
-            Transform::from_xyz(*x, *y, 0.0), 
-            ChargerStation, 
-            Booked(false)
-        ));
+        spawn_station(commands, StationKind::Charger, StationId(next_station_id), station);
+        next_station_id += 1;
     }
+}
 
-    // Robots
-    for i in 0..config.robot_count 
+// One entity per rack or wall, with a child per box or wall segment, drawn underneath stations and robots
+pub fn spawn_obstacles(commands: &mut Commands, config: &SimulationConfig)
+{
+    for obstacle in &config.obstacles 
     {
-        commands.spawn((
-            Sprite::from_color(Color::WHITE, 
-            Vec2::new(30.0, 30.0)),
-            Transform::from_xyz(0.0 + (i as f32 * 100.0), 50.0, 0.0),
-            Robot,
-            Speed(config.robot_speed),
-            TargetPosition(Vec3::ZERO), 
-            RobotState::Idle,
-            RobotTimers {
-                work: Timer::from_seconds(1.0, TimerMode::Once),
-                charge: Timer::from_seconds(config.charging_time, TimerMode::Once),
-            }, 
-            ReservedStation(None),
-            Battery(100.0), 
-            SavedMemory(None) 
-        ));
+        commands.spawn((Obstacle, Transform::default(), Visibility::default()))
+            .with_children(|parts| {
+                for shape in obstacle.shapes() {
+                    parts.spawn(obstacle_part(shape));
+                }
+            });
     }
 }
 
+fn obstacle_part(shape: ObstacleShape) -> impl Bundle
+{
+    let color = Color::srgb(0.35, 0.35, 0.4);
+    let (size, transform) = match shape {
+        ObstacleShape::Box { center, half_size } => {
+            (half_size * 2.0, Transform::from_translation(center.extend(-1.0)))
+        }
+        ObstacleShape::Segment { start, end, half_thickness } => {
+            let along = end - start;
+            // Extended by the thickness so consecutive segments meet without gaps at the corners
+            let size = Vec2::new(along.length() + half_thickness * 2.0, half_thickness * 2.0);
+            let transform = Transform::from_translation(((start + end) / 2.0).extend(-1.0))
+                .with_rotation(Quat::from_rotation_z(along.to_angle()));
+            (size, transform)
+        }
+    };
+    (Sprite::from_color(color, size), transform, shape)
+}
+
+pub fn spawn_station(commands: &mut Commands, kind: StationKind, id: StationId, station: &StationDef) -> Entity
+{
+    let (x, y) = station.position;
+    let power = station.power;
+    let (color, size) = match kind {
+        StationKind::Pickup => (Color::srgb(0.0, 1.0, 0.0), STATION_SIZE),
+        StationKind::Dropoff => (Color::srgb(0.0, 0.0, 1.0), STATION_SIZE),
+        StationKind::Charger => (Color::srgb(1.0, 1.0, 0.0), CHARGER_SIZE),
+    };
+
+    let mut station = commands.spawn((
+        Sprite::from_color(color, Vec2::splat(size)), 
+        Transform::from_xyz(x, y, 0.0), 
+        id,
+        Bays::new(station.docks().into_iter().map(|dock| dock.extend(0.0)).collect()),
+        StationQueue { spots: station.queue_spots().map(|spot| spot.extend(0.0)).collect(), line: Vec::new() },
+        station.service
+    ));
+    match kind {
+        StationKind::Pickup => station.insert(PickupStation),
+        StationKind::Dropoff => station.insert(DropoffStation),
+        StationKind::Charger => station.insert((ChargerStation, ChargerPower(power))),
+    };
+    station.id()
+}
+
+// The id doubles as the robot's slot in the spawn line
+pub fn spawn_slot(id: RobotId) -> Vec3
+{
+    Vec3::new(0.0 + (id.0 as f32 * 100.0), 50.0, 0.0)
+}
+
+pub fn spawn_robot(commands: &mut Commands, config: &SimulationConfig, id: RobotId) -> Entity
+{
+    commands.spawn((
+        Sprite::from_color(Color::WHITE, 
+        Vec2::splat(ROBOT_SIZE)),
+        Transform::from_translation(spawn_slot(id)),
+        Robot,
+        id,
+        Footprint(ROBOT_SIZE / 2.0),
+        (
+            Speed(config.robot_speed),
+            Kinematics {
+                max_acceleration: config.max_acceleration,
+                max_angular_rate: config.max_angular_rate,
+            },
+            Velocity::default(),
+            Heading::default(),
+        ),
+        TargetPosition(Vec3::ZERO), 
+        RobotState::Idle,
+        RobotTimers {
+            work: Timer::from_seconds(1.0, TimerMode::Once),
+        }, 
+        (ReservedStation(None), AssignedOrder::default()),
+        (Battery(100.0), BatteryCapacity(config.battery.capacity_wh), BatteryHealth::default()), 
+        SavedMemory(None),
+        WallFollow::default(),
+        NavPath::default(),
+        Stall::default()
+    )).id()
+}
+
 // --- LOGIC ---
 
+type MovingRobot = (
+    Entity,
+    &'static mut Transform,
+    &'static TargetPosition,
+    &'static Speed,
+    &'static Kinematics,
+    &'static RobotState,
+    &'static mut WallFollow,
+    &'static mut NavPath,
+    &'static mut Velocity,
+    &'static mut Heading,
+    &'static Stall,
+);
+
+#[allow(clippy::too_many_arguments)]
 pub fn movement_system(
     time: Res<Time>,
     config: Res<SimulationConfig>,
-    mut param_set: ParamSet<(
-        Query<(Entity, &Transform), With<Robot>>,
-        Query<(Entity, &mut Transform, &TargetPosition, &Speed, &RobotState)>
-    )>
+    grid: Res<SpatialGrid>,
+    mut robot_query: Query<MovingRobot>,
+    wall_query: Query<&ObstacleShape>,
+    mut nearby: Local<Vec<Neighbour>>,
+    mut walls: Local<Vec<ObstacleShape>>
 ) 
 {
-    // 1. Snapshot all obstacles
-    // Optimization: explicitly reserve capacity if you know N to avoid re-allocations
-    let obstacle_count = param_set.p0().iter().len();
-    let obstacles: Vec<(Entity, Vec3)> = param_set.p0().iter()
-        .map(|(e, t)| (e, t.translation))
-        .collect(); // Note: vectors allocate, doing this every frame is costly for huge N
+    // 1. Other robots come from the spatial grid, built from positions at the start of the tick.
+    // Walls don't move, and there are few enough of them to check all of them.
+    walls.clear();
+    walls.extend(wall_query.iter().copied());
+    let avoidance = avoidance_model(config.avoidance);
+    let dt = time.delta_secs();
 
     // 2. Update robots
-    // FIX: Add '_entity' if you aren't using it, but here you ARE using it in calculate_avoidance_force.
-    // If you still get a warning, it means calculate_avoidance_force isn't using the argument.
-    for (entity, mut transform, target, speed, state) in param_set.p1().iter_mut() {    
+    for (entity, mut transform, target, speed, kinematics, state, mut wall_follow, mut path, mut velocity, mut heading, stall) in robot_query.iter_mut() {    
         
         // Skip dead robots
-        if *state == RobotState::Dead { continue; }
+        if *state == RobotState::Dead { 
+            velocity.0 = Vec3::ZERO;
+            continue; 
+        }
 
         // Filter moving states
-        let should_move = matches!(state, 
-            RobotState::MovingToPickup | 
-            RobotState::MovingToDropoff | 
-            RobotState::MovingToCharger
-        );
-        if !should_move { continue; }
+        if !state.is_moving() { 
+            velocity.0 = Vec3::ZERO;
+            continue; 
+        }
 
         let current_pos = transform.translation;
 
-        // Collision avoidance
-        let (separation_vector, critical_overlap) = calculate_avoidance_force(
-            entity, 
-            current_pos, 
-            &obstacles, 
-            config.collision_radius
-        );
-
-        // A. Goal Vector
-        let target_vec = target.0 - current_pos;
-        let dist_to_target = target_vec.length();
+        // Robots in line wait at their spot until the one ahead moves on
+        if state.is_queued() && current_pos.distance(target.0) < config.state_change_radius {
+            velocity.0 = Vec3::ZERO;
+            continue;
+        }
+
+        // Cooperative paths are conflict-free already: keep to the schedule, other robots don't push.
+        // The planner doesn't model acceleration, so the schedule is kept exactly and the robot just faces the way it goes.
+        if config.navigation == NavigationMode::Cooperative {
+            let next_pos = push_out_of_walls(path.follow_schedule(current_pos, time.elapsed_secs(), dt, speed.0), &walls);
+            let step = next_pos - current_pos;
+            if step.length_squared() > 0.0 {
+                heading.0 = step.y.atan2(step.x);
+                transform.rotation = Quat::from_rotation_z(heading.0);
+            }
+            transform.translation = next_pos;
+            velocity.0 = step / dt;
+            path.track_progress(target.0.distance(transform.translation), dt);
+            continue;
+        }
+
+        // Collision avoidance: only robots within the radius count, taken in id order so every run adds them up the same way
+        nearby.clear();
+        nearby.extend(grid.nearby(current_pos, config.collision_radius)
+            .filter(|other| current_pos.distance(other.position) < config.collision_radius));
+        nearby.sort_unstable_by_key(|other| other.id);
+
+        // A. Goal Vector: head for the next waypoint of the planned path, the last one being the target itself
+        let waypoint = path.next_waypoint(current_pos).unwrap_or(target.0);
+        let waypoint_vec = waypoint - current_pos;
+        let dist_to_waypoint = waypoint_vec.length();
+        let dist_to_target = target.0.distance(current_pos);
         
         // Logic Check: Don't normalize if already at target
-        let goal_dir = if dist_to_target > 0.01 { 
-            target_vec.normalize() 
+        let goal_dir = if dist_to_waypoint > 0.01 { 
+            waypoint_vec.normalize() 
         } else { 
             Vec3::ZERO 
         };
+        // Robots recovering from a stall back away for a moment first
+        let goal_dir = stall.backing_off().unwrap_or(goal_dir);
+        let goal_dir = steer_around_walls(current_pos, goal_dir, dist_to_waypoint, &walls, &mut wall_follow.0);
 
-        // B. Apply Forces
-        // FIX: Initialize directly from the if/else block to silence the warning
-        let final_direction = if critical_overlap {
-            // Emergency avoidance: Ignore goal, just run away
-            separation_vector
+        // Simple Arrival Logic (Slow down when close), starting early enough to brake in time
+        // Prevents the robot from jittering back and forth over the target
+        let slow_radius = (speed.0 * speed.0 / (2.0 * kinematics.max_acceleration)).max(10.0);
+        let current_speed = if dist_to_target < slow_radius {
+            speed.0 * (dist_to_target / slow_radius).sqrt().clamp(0.1, 1.0)
         } else {
-            // Standard Navigation: Mix goal and avoidance
-            goal_dir + separation_vector
+            speed.0
         };
 
+        // B. Let the avoidance model pick a velocity around the other robots
+        let desired = avoidance.velocity(&AvoidanceInput {
+            entity,
+            position: current_pos,
+            velocity: velocity.0,
+            priority: stall.has_priority(time.elapsed_secs()),
+            goal_dir,
+            max_speed: current_speed,
+            neighbours: &nearby,
+            walls: &walls,
+            collision_radius: config.collision_radius,
+            dt,
+        });
+
         // C. Move
-        // We use length_squared() because it's faster (no square root)
-        if final_direction.length_squared() > 0.0001 {
-            let move_dir = final_direction.normalize();
-            
-            // OPTIONAL: Simple Arrival Logic (Slow down when close)
-            // Prevents the robot from jittering back and forth over the target
-            let current_speed = if dist_to_target < 10.0 {
-                speed.0 * (dist_to_target / 10.0).clamp(0.1, 1.0)
-            } else {
-                speed.0
-            };
+        drive(&mut transform, &mut heading, &mut velocity, desired, kinematics, &walls, dt);
 
-            transform.translation += move_dir * speed.0 * time.delta_secs();
-        }
+        // D. Ask for a new path if the robot is getting nowhere
+        path.track_progress(waypoint.distance(transform.translation), dt);
     }
 }
 
+// Drives a robot one tick towards the desired velocity, within its acceleration and turning limits
+fn drive(
+    transform: &mut Transform,
+    heading: &mut Heading,
+    velocity: &mut Velocity,
+    desired: Vec3,
+    kinematics: &Kinematics,
+    walls: &[ObstacleShape],
+    dt: f32,
+)
+{
+    let speed = velocity.0.dot(Vec3::new(heading.0.cos(), heading.0.sin(), 0.0));
+    let (next_pos, next_heading, next_speed) = unicycle_step(transform.translation, heading.0, speed, desired, kinematics, dt);
+    transform.translation = push_out_of_walls(next_pos, walls);
+    transform.rotation = Quat::from_rotation_z(next_heading);
+    heading.0 = next_heading;
+    velocity.0 = Vec3::new(next_heading.cos(), next_heading.sin(), 0.0) * next_speed;
+}
+
 // --- STATE MACHINE ---
+
+type WorkingRobot = (
+    Entity,
+    &'static RobotId,
+    &'static mut RobotState,
+    &'static mut TargetPosition,
+    &'static Transform,
+    &'static mut RobotTimers,
+    &'static mut ReservedStation,
+    (&'static mut Battery, &'static BatteryCapacity, &'static mut BatteryHealth),
+    &'static mut SavedMemory,
+    &'static mut AssignedOrder,
+);
+
+type StationBays = (Entity, &'static StationId, &'static Transform, &'static mut Bays);
+
+#[allow(clippy::too_many_arguments)]
 pub fn robot_state_machine(
     time: Res<Time>,
     config: Res<SimulationConfig>,
-    mut robot_query: Query<(Entity, &mut RobotState, &mut TargetPosition, &Transform, &mut RobotTimers, &mut ReservedStation, &mut Battery, &mut SavedMemory), With<Robot>>,
-    mut pickup_query: Query<(Entity, &Transform, &mut Booked), (With<PickupStation>, Without<DropoffStation>, Without<ChargerStation>)>,
-    mut dropoff_query: Query<(Entity, &Transform, &mut Booked), (With<DropoffStation>, Without<PickupStation>, Without<ChargerStation>)>,
-    mut charger_query: Query<(Entity, &Transform, &mut Booked), (With<ChargerStation>, Without<PickupStation>, Without<DropoffStation>)>
+    mut transitions: MessageWriter<RobotStateChanged>,
+    mut completed: MessageWriter<OrderCompleted>,
+    mut rng: ResMut<SimRng>,
+    station_ids: Query<&StationId>,
+    service_times: Query<&ServiceTime>,
+    charger_powers: Query<&ChargerPower>,
+    mut robot_query: Query<WorkingRobot, With<Robot>>,
+    mut pickup_query: Query<StationBays, OnlyPickups>,
+    mut dropoff_query: Query<StationBays, OnlyDropoffs>,
+    mut charger_query: Query<StationBays, OnlyChargers>
 ) 
 {
-    for (robot_entity, mut state, mut target, transform, mut timer, mut reserved, mut battery, mut memory) in &mut robot_query {
+    // Robots release stations in id order, so runs are reproducible
+    let robot_order = entities_in_id_order(robot_query.iter().map(|(e, id, ..)| (e, *id)));
+    let with_orders = config.orders != OrderSource::Unlimited;
+
+    for robot_entity in robot_order {
+        let Ok((_, robot_id, mut state, mut target, transform, mut timer, mut reserved, (mut battery, capacity, mut health), mut memory, mut assigned)) = robot_query.get_mut(robot_entity) else { continue };
         
         if *state == RobotState::Dead { continue; }
 
+        let robot_id = *robot_id;
+        let now = time.elapsed_secs();
+        let mut change_state = |state: &mut RobotState, to: RobotState, station: Option<Entity>, battery: f32| {
+            transitions.write(RobotStateChanged {
+                robot: robot_id,
+                from: *state,
+                to,
+                station: station.and_then(|entity| station_ids.get(entity).ok().copied()),
+                battery,
+                sim_time: now,
+            });
+            *state = to;
+        };
+
         match *state {
             // (Idle, MovingToPickup, PickingUp, WaitingForDropoff, MovingToDropoff, DroppingOff)
-            RobotState::Idle => 
-            {
-                 for (pickup_entity, pickup_transform, mut booked) in &mut pickup_query 
-                 {
-                    if !booked.0 
-                    {
-                        booked.0 = true; 
-                        *state = RobotState::MovingToPickup;
-                        target.0 = pickup_transform.translation;
-                        reserved.0 = Some(pickup_entity);
-                        break; 
-                    }
-                }
-            }
+            // Robots waiting for a station, or in line for one, get it from dispatch_system
+            RobotState::Idle | RobotState::MovingToPark |
+            RobotState::WaitingForDropoff | RobotState::WaitingForCharger |
+            RobotState::QueuedForDropoff | RobotState::QueuedForCharger => {}
             RobotState::MovingToPickup => 
             {
                 if transform.translation.distance(target.0) < config.state_change_radius 
                 {
-                    *state = RobotState::PickingUp;
-                    timer.work.reset(); 
+                    change_state(&mut state, RobotState::PickingUp, reserved.0, battery.0);
+                    timer.work = work_timer(reserved.0, &service_times, &mut rng);
                 }
             }
             RobotState::PickingUp => 
@@ -198,28 +381,18 @@ pub fn robot_state_machine(
                 timer.work.tick(time.delta());
                 if timer.work.just_finished() 
                 {
-                    if let Some(station_entity) = reserved.0 
+                    change_state(&mut state, RobotState::WaitingForDropoff, reserved.0, battery.0);
+
+                    // A robot with an order waits for its own dropoff, which can take a while:
+                    // it keeps its pickup bay booked meanwhile, so no one is sent to the bay it is standing on
+                    if assigned.0.is_none()
                     {
-                        if let Ok((_, _, mut booked)) = pickup_query.get_mut(station_entity) 
+                        if let Some(station_entity) = reserved.0
+                            && let Ok((_, _, _, mut bays)) = pickup_query.get_mut(station_entity)
                         {
-                            booked.0 = false;
+                            bays.release(robot_entity);
                         }
-                    }
-                    reserved.0 = None;
-                    *state = RobotState::WaitingForDropoff;
-                }
-            }
-            RobotState::WaitingForDropoff => 
-            {
-                for (dropoff_entity, dropoff_transform, mut booked) in &mut dropoff_query 
-                {
-                    if !booked.0 
-                    {
-                        booked.0 = true; 
-                        *state = RobotState::MovingToDropoff;
-                        target.0 = dropoff_transform.translation;
-                        reserved.0 = Some(dropoff_entity);
-                        break; 
+                        reserved.0 = None;
                     }
                 }
             }
@@ -227,8 +400,8 @@ pub fn robot_state_machine(
             {
                 if transform.translation.distance(target.0) < config.state_change_radius 
                 {
-                    *state = RobotState::DroppingOff;
-                    timer.work.reset();
+                    change_state(&mut state, RobotState::DroppingOff, reserved.0, battery.0);
+                    timer.work = work_timer(reserved.0, &service_times, &mut rng);
                 }
             }
             RobotState::DroppingOff => 
@@ -236,32 +409,26 @@ pub fn robot_state_machine(
                 timer.work.tick(time.delta());
                 if timer.work.just_finished() 
                 {
-                    if let Some(station_entity) = reserved.0 
+                    if let Some(station_entity) = reserved.0
+                        && let Ok((_, _, _, mut bays)) = dropoff_query.get_mut(station_entity)
                     {
-                        if let Ok((_, _, mut booked)) = dropoff_query.get_mut(station_entity) 
-                        {
-                            booked.0 = false;
-                        }
+                        bays.release(robot_entity);
+                    }
+                    if let Some(order) = assigned.0.take()
+                    {
+                        completed.write(OrderCompleted {
+                            order: order.id,
+                            robot: robot_id,
+                            pickup: station_ids.get(order.pickup).ok().copied(),
+                            dropoff: reserved.0.and_then(|entity| station_ids.get(entity).ok().copied()),
+                            priority: order.priority,
+                            created: order.created,
+                            assigned: order.assigned.unwrap_or(order.created),
+                            sim_time: now,
+                        });
                     }
+                    change_state(&mut state, RobotState::Idle, reserved.0, battery.0);
                     reserved.0 = None;
-                    *state = RobotState::Idle; 
-                }
-            }
-
-            RobotState::WaitingForCharger => 
-            {
-                for (charger_entity, charger_transform, mut booked) in &mut charger_query 
-                {
-                     if !booked.0 
-                     {
-                        booked.0 = true;
-                        *state = RobotState::MovingToCharger;
-                        target.0 = charger_transform.translation;
-                        // We overwrite reserved.0 with the Charger ID temporarily.
-                        // This is fine because we cached the old ID in 'memory'.
-                        reserved.0 = Some(charger_entity); 
-                        break;
-                     }
                 }
             }
 
@@ -269,33 +436,55 @@ pub fn robot_state_machine(
             {
                 if transform.translation.distance(target.0) < config.state_change_radius
                 {
-                    *state = RobotState::Charging;
-                    timer.charge.reset();
+                    change_state(&mut state, RobotState::Charging, reserved.0, battery.0);
+
+                    // The discharge ends here. A battery worn past replace_below is swapped for a new, full one
+                    health.end_discharge(battery.0, config.battery.cycle_fade, config.battery.dod_exponent);
+                    if config.battery.replace_below.is_some_and(|below| health.health < below)
+                    {
+                        health.replace();
+                        battery.0 = 100.0;
+                    }
                 }
             }
             RobotState::Charging => 
             {
-                timer.charge.tick(time.delta());
-                
-                battery.0 += 25.0 * time.delta_secs(); 
+                // A charger without power can't charge anyone: the robot gives up its bay and asks for another charger
+                let Some(power) = reserved.0.and_then(|entity| charger_powers.get(entity).ok()) else {
+                    if let Some(station_entity) = reserved.0
+                        && let Ok((_, _, _, mut bays)) = charger_query.get_mut(station_entity)
+                    {
+                        bays.release(robot_entity);
+                    }
+                    change_state(&mut state, RobotState::WaitingForCharger, reserved.0, battery.0);
+                    reserved.0 = None;
+                    continue;
+                };
+                battery.0 += charge_rate(battery.0, power.0, capacity.0 * health.capacity_share(), config.battery.cv_from) * time.delta_secs();
                 if battery.0 > 100.0 { battery.0 = 100.0; }
+                let charged = battery.0 >= config.battery.charge_until;
+                if charged { health.charged_to = battery.0; }
 
-                if timer.charge.just_finished() 
+                // An order can keep a robot waiting for its dropoff a while: it waits on the charger, keeping its bay booked
+                let waits_here = with_orders && memory.0.is_some_and(|(saved_state, ..)| saved_state == RobotState::WaitingForDropoff);
+                if charged && waits_here
+                {
+                    change_state(&mut state, RobotState::WaitingForDropoff, reserved.0, battery.0);
+                    memory.0 = None;
+                }
+                else if charged
                 {
                     // 1. Release the Charger
-                    if let Some(station_entity) = reserved.0 
+                    if let Some(station_entity) = reserved.0
+                        && let Ok((_, _, _, mut bays)) = charger_query.get_mut(station_entity)
                     {
-                        if let Ok((_, _, mut booked)) = charger_query.get_mut(station_entity) 
-                        {
-                            booked.0 = false;
-                        }
+                        bays.release(robot_entity);
                     }
                     
                     // 2. Resume memory (with key)
                     if let Some((saved_state, saved_target, saved_key)) = memory.0 
                     {
-                        println!("Charged! Resuming {:?}...", saved_state);
-                        *state = saved_state;
+                        change_state(&mut state, saved_state, saved_key, battery.0);
                         target.0 = saved_target;
                     
                         // when bot arrives at the station, it will have the correct ID to unlock it.
@@ -303,7 +492,7 @@ pub fn robot_state_machine(
                     } 
                     else 
                     {
-                        *state = RobotState::Idle;
+                        change_state(&mut state, RobotState::Idle, reserved.0, battery.0);
                         reserved.0 = None;
                     }
                     memory.0 = None;
@@ -314,81 +503,156 @@ pub fn robot_state_machine(
     }
 }
 
+// A fresh work timer for a visit to `station`, as long as its service time draws
+fn work_timer(station: Option<Entity>, service_times: &Query<&ServiceTime>, rng: &mut SimRng) -> Timer
+{
+    let service = station.and_then(|entity| service_times.get(entity).ok()).copied().unwrap_or_default();
+    Timer::from_seconds(service.sample(&mut rng.0), TimerMode::Once)
+}
+
 // --- BATTERY SYSTEM ---
+
+const CV_CUTOFF: f32 = 0.05; // share of the charger's power left at the end of the constant voltage phase
+
+/// How fast a battery at `charge` (%) charges on a charger of `power` watts, in % per second.
+/// Constant current up to `cv_from`, then constant voltage: the power tapers off linearly towards full,
+/// down to CV_CUTOFF of the charger's, so a battery still fills up in finite time.
+pub fn charge_rate(charge: f32, power: f32, capacity_wh: f32, cv_from: f32) -> f32
+{
+    let taper = if charge <= cv_from { 1.0 } else { ((100.0 - charge) / (100.0 - cv_from)).max(CV_CUTOFF) };
+    percent_per_second(power * taper, capacity_wh)
+}
+
+// How fast `watts` fill or drain a battery holding `capacity_wh`, in % per second
+fn percent_per_second(watts: f32, capacity_wh: f32) -> f32
+{
+    watts / 3600.0 / capacity_wh * 100.0
+}
+
+type DrainingRobot = (
+    &'static RobotId,
+    (&'static mut Battery, &'static BatteryCapacity, &'static mut BatteryHealth),
+    &'static mut Sprite,
+    &'static mut RobotState,
+    &'static mut SavedMemory,
+    &'static TargetPosition,
+    &'static Transform,
+    &'static ReservedStation,
+);
+
 pub fn battery_system(
     time: Res<Time>,
     config: Res<SimulationConfig>,
-    mut query: Query<(&mut Battery, &mut Sprite, &mut RobotState, &mut SavedMemory, &TargetPosition, &ReservedStation), With<Robot>>
+    mut transitions: MessageWriter<RobotStateChanged>,
+    station_ids: Query<&StationId>,
+    mut query: Query<DrainingRobot, With<Robot>>
 ) 
 {
-    for (mut battery, mut sprite, mut state, mut memory, target, reserved) in &mut query {
+    for (robot_id, (mut battery, capacity, mut health), mut sprite, mut state, mut memory, target, transform, reserved) in &mut query {
         
-        if *state == RobotState::Dead || *state == RobotState::Charging {
+        if *state == RobotState::Dead {
             continue;
         }
 
-        let is_moving = match *state {
-            RobotState::MovingToPickup | RobotState::MovingToDropoff | RobotState::MovingToCharger => true,
-            _ => false
-        };
+        // Batteries age with time, charging or not
+        health.health = (health.health - config.battery.calendar_fade_per_day * time.delta_secs() / 86_400.0).max(0.0);
+        if *state == RobotState::Charging {
+            continue;
+        }
+
+        // Robots in line drain like idle ones while they stand at their spot.
+        // A worn battery holds less, so the same power drains it faster
+        let standing_in_line = state.is_queued() && transform.translation.distance(target.0) < config.state_change_radius;
+        let power = if state.is_moving() && !standing_in_line { config.move_power_w } else { config.idle_power_w };
+        battery.0 -= percent_per_second(power, capacity.0 * health.capacity_share()) * time.delta_secs();
 
-        let drain_rate = if is_moving { config.drain_move } else { config.drain_idle };
-        battery.0 -= drain_rate * time.delta_secs();
+        let mut change_state = |state: &mut RobotState, to: RobotState| {
+            transitions.write(RobotStateChanged {
+                robot: *robot_id,
+                from: *state,
+                to,
+                station: reserved.0.and_then(|entity| station_ids.get(entity).ok().copied()),
+                battery: battery.0,
+                sim_time: time.elapsed_secs(),
+            });
+            *state = to;
+        };
 
         // Death Check
         if battery.0 < config.dead_battery_threshold {
-            *state = RobotState::Dead;
-            sprite.color = Color::BLACK; 
-            println!("Robot Died!");
+            change_state(&mut state, RobotState::Dead);
+            sprite.color = robot_color(RobotState::Dead, battery.0);
             continue;
         }
 
         // Low Battery Check
-        let charging_related = match *state {
-            RobotState::WaitingForCharger | RobotState::MovingToCharger | RobotState::Charging => true,
-            _ => false
-        };
+        let charging_related = matches!(*state,
+            RobotState::WaitingForCharger | RobotState::QueuedForCharger | RobotState::MovingToCharger | RobotState::Charging);
 
         if battery.0 < config.low_battery_threshold && !charging_related {
-            let resume_state = match *state {
-                RobotState::PickingUp => RobotState::MovingToPickup,
-                RobotState::DroppingOff => RobotState::MovingToDropoff,
-                _ => *state,
+            let (resume_state, key) = match *state {
+                RobotState::PickingUp => (RobotState::MovingToPickup, reserved.0),
+                RobotState::DroppingOff => (RobotState::MovingToDropoff, reserved.0),
+                // The place in line isn't kept: the robot asks for a dropoff again after charging
+                RobotState::QueuedForDropoff => (RobotState::WaitingForDropoff, None),
+                _ => (*state, reserved.0),
             };
 
-            memory.0 = Some((resume_state, target.0, reserved.0));
-            *state = RobotState::WaitingForCharger;
-            println!("Low Battery! Finding charger...");
+            memory.0 = Some((resume_state, target.0, key));
+            change_state(&mut state, RobotState::WaitingForCharger);
         }
 
         // Color Update
-        if battery.0 > 50.0 {
-            sprite.color = Color::WHITE;
-        } else {
-            sprite.color = Color::srgb(1.0, 0.5, 0.0); // Orange
-        }
+        sprite.color = robot_color(*state, battery.0);
     }
 } 
 
-// // --- CAMERA CONTROLS ---
-// pub fn camera_controls(
-//     mut motion_events: EventReader<MouseMotion>,
-//     mut scroll_events: EventReader<MouseWheel>,
-//     mouse_buttons: Res<ButtonInput<MouseButton>>,
-//     mut query: Query<(&mut Transform, &mut Projection), With<Camera>>,
-// ) {
-//     if let Ok((mut transform, mut projection)) = query.single_mut() {
-//         if let Projection::Orthographic(ortho) = &mut *projection {
-//             if mouse_buttons.pressed(MouseButton::Right) {
-//                 for ev in motion_events.read() {
-//                     transform.translation.x -= ev.delta.x * ortho.scale;
-//                     transform.translation.y += ev.delta.y * ortho.scale;
-//                 }
-//             }
-
-//             for ev in scroll_events.read() {
-//                 ortho.scale = (ortho.scale - ev.y * 0.1).clamp(0.1, 5.0);
-//             }
-//         }
-//     }
-// }
\ No newline at end of file
+/// Robot sprite colour: black when dead, orange below half charge, white otherwise.
+pub fn robot_color(state: RobotState, battery: f32) -> Color {
+    if state == RobotState::Dead {
+        Color::BLACK
+    } else if battery > 50.0 {
+        Color::WHITE
+    } else {
+        Color::srgb(1.0, 0.5, 0.0) // Orange
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn constant_current_charges_at_the_chargers_full_power() {
+        // 1.8 kW into 2 Wh: 25% per second, whatever the charge up to cv_from
+        for charge in [0.0, 30.0, 80.0] {
+            assert!(close(charge_rate(charge, 1800.0, 2.0, 80.0), 25.0), "at {charge}%");
+        }
+        // Twice the battery charges half as fast, half the charger too
+        assert!(close(charge_rate(50.0, 1800.0, 4.0, 80.0), 12.5));
+        assert!(close(charge_rate(50.0, 900.0, 2.0, 80.0), 12.5));
+    }
+
+    #[test]
+    fn constant_voltage_tapers_off_towards_full() {
+        // Linear from the full rate at cv_from to nothing at 100%
+        assert!(close(charge_rate(85.0, 1800.0, 2.0, 80.0), 25.0 * 0.75));
+        assert!(close(charge_rate(90.0, 1800.0, 2.0, 80.0), 25.0 * 0.5));
+        assert!(close(charge_rate(95.0, 1800.0, 2.0, 80.0), 25.0 * 0.25));
+        assert!(charge_rate(90.0, 1800.0, 2.0, 80.0) > charge_rate(91.0, 1800.0, 2.0, 80.0));
+    }
+
+    #[test]
+    fn charging_never_drops_below_the_cutoff() {
+        // Past 99% the taper would go under CV_CUTOFF, so the battery still fills up
+        let floor = 25.0 * CV_CUTOFF;
+        for charge in [99.0, 99.5, 100.0] {
+            assert!(close(charge_rate(charge, 1800.0, 2.0, 80.0), floor), "at {charge}%");
+        }
+        assert!(charge_rate(98.0, 1800.0, 2.0, 80.0) > floor);
+    }
+}
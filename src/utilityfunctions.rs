@@ -1,17 +1,37 @@
 use bevy::prelude::*;
+use std::f32::consts::{PI, TAU};
+
+use crate::components::{Kinematics, ObstacleShape, ROBOT_SIZE};
+use crate::spatial::Neighbour;
+
+// Walls start pushing once a robot's edge is within half a robot width of them
+const WALL_RANGE: f32 = ROBOT_SIZE;
 
 /// Calculates the separation force based on nearby obstacles (Boids logic).
 /// Returns a tuple: (Separation Force Vector, Critical Overlap Boolean)
 pub fn calculate_avoidance_force(
     current_entity: Entity,
+    current_priority: bool, // right of way over robots without it, whatever the entity order
     current_pos: Vec3,
-    obstacles: &[(Entity, Vec3)], // Pass a slice of obstacles
+    neighbours: &[Neighbour], // robots around, including possibly this one
+    walls: &[ObstacleShape],
     collision_radius: f32,
 ) -> (Vec3, bool) {
     let mut separation_vector = Vec3::ZERO;
     let mut critical_overlap = false;
 
-    for (other_entity, other_pos) in obstacles 
+    // Walls never yield, and only push over a short range so stations next to racks stay reachable
+    for wall in walls 
+    {
+        let (distance, normal) = wall.distance_and_normal(current_pos.truncate());
+        if distance < WALL_RANGE 
+        {
+            let strength = 1.0 - (distance / WALL_RANGE).max(0.0);
+            separation_vector += normal.extend(0.0) * strength;
+        }
+    }
+
+    for Neighbour { entity: other_entity, position: other_pos, priority: other_priority, .. } in neighbours 
     {
         // Skip self
         if current_entity == *other_entity 
@@ -27,13 +47,13 @@ pub fn calculate_avoidance_force(
             // The closer they are, the stronger the force (0.0 to 1.0)
             let strength = 1.0 - (distance / collision_radius);
 
-            // Tie-breaker logic: Lower ID yields (moves away faster), Higher ID stays course
-            if current_entity < *other_entity 
+            // Tie-breaker logic: Lower ID yields (moves away faster), Higher ID stays course,
+            // unless only one of them has been given priority
+            if (current_priority, current_entity) < (*other_priority, *other_entity) 
             {
                 //"yielder" - move away slightly
                 separation_vector += away_direction * strength * 0.5;
-            } else 
-            {
+            } else {
                 if distance < (collision_radius * 0.5) {
                     critical_overlap = true;
                 }
@@ -44,3 +64,117 @@ pub fn calculate_avoidance_force(
     (separation_vector, critical_overlap)
 }
 
+/// Bends a goal direction around nearby walls. While the goal points into a wall the robot follows
+/// the wall instead, on the side the goal leaned towards when it first met it (counter-clockwise if head-on).
+/// `follow` remembers that side (+1 counter-clockwise, -1 clockwise) until the way to the goal is clear,
+/// so a robot doesn't dither when the goal sits straight behind a wall.
+/// Walls beyond the target (`target_distance` away) are ignored.
+pub fn steer_around_walls(
+    current_pos: Vec3,
+    direction: Vec3,
+    target_distance: f32,
+    walls: &[ObstacleShape],
+    follow: &mut Option<f32>,
+) -> Vec3 {
+    let mut steered = direction;
+    let mut blocked = false;
+
+    for wall in walls 
+    {
+        let (distance, normal) = wall.distance_and_normal(current_pos.truncate());
+        if distance >= WALL_RANGE * 2.0 || distance >= target_distance { continue; }
+
+        let normal = normal.extend(0.0);
+        if direction.dot(normal) >= 0.0 { continue; }
+        blocked = true;
+
+        if distance < WALL_RANGE 
+        {
+            let tangent = Vec3::new(-normal.y, normal.x, 0.0);
+            let side = *follow.get_or_insert(if direction.dot(tangent) >= 0.0 { 1.0 } else { -1.0 });
+            steered = tangent * side;
+        }
+    }
+
+    if !blocked {
+        *follow = None;
+    }
+    steered
+}
+
+/// Moves a robot's centre back out of any wall it overlaps, so walls stay solid whatever the forces did.
+pub fn push_out_of_walls(position: Vec3, walls: &[ObstacleShape]) -> Vec3 {
+    let radius = ROBOT_SIZE / 2.0;
+    let mut position = position;
+    for wall in walls 
+    {
+        let (distance, normal) = wall.distance_and_normal(position.truncate());
+        if distance < radius {
+            position += normal.extend(0.0) * (radius - distance);
+        }
+    }
+    position
+}
+
+/// Advances a differential-drive robot (unicycle model) one tick towards a desired velocity.
+/// The robot turns towards the desired direction no faster than `max_angular_rate`, and drives the part
+/// of the desired velocity that lies along its heading, in reverse if the desired direction is behind it.
+/// Its speed changes by at most `max_acceleration` per second. `speed` is signed, negative when reversing.
+/// Returns the new position, heading and speed.
+pub fn unicycle_step(
+    position: Vec3,
+    heading: f32,
+    speed: f32,
+    desired: Vec3,
+    kinematics: &Kinematics,
+    dt: f32,
+) -> (Vec3, f32, f32) {
+    let mut heading = heading;
+    if desired.length_squared() > 0.0001 {
+        let error = wrap_angle(desired.y.atan2(desired.x) - heading);
+        let max_turn = kinematics.max_angular_rate * dt;
+        heading = wrap_angle(heading + error.clamp(-max_turn, max_turn));
+    }
+
+    let forward = Vec3::new(heading.cos(), heading.sin(), 0.0);
+    let target_speed = desired.dot(forward);
+    let max_change = kinematics.max_acceleration * dt;
+    let speed = speed + (target_speed - speed).clamp(-max_change, max_change);
+
+    (position + forward * speed * dt, heading, speed)
+}
+
+/// Brings an angle into (-PI, PI].
+pub fn wrap_angle(angle: f32) -> f32 {
+    let wrapped = (angle + PI).rem_euclid(TAU) - PI;
+    if wrapped <= -PI { wrapped + TAU } else { wrapped }
+}
+
+/// Orders entities by their id component, so iteration doesn't depend on archetype/table layout.
+pub fn entities_in_id_order<Id: Ord + Copy>(items: impl Iterator<Item = (Entity, Id)>) -> Vec<Entity> {
+    let mut items: Vec<(Id, Entity)> = items.map(|(entity, id)| (id, entity)).collect();
+    items.sort_unstable();
+    items.into_iter().map(|(_, entity)| entity).collect()
+}
+
+/// FNV-1a hash over robot ids, exact positions and states.
+/// Two runs of the same scenario and seed must end with the same checksum.
+pub fn state_checksum(robots: impl Iterator<Item = (u32, Vec3, u8)>) -> u64 {
+    let mut robots: Vec<(u32, Vec3, u8)> = robots.collect();
+    robots.sort_unstable_by_key(|(id, ..)| *id);
+
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut feed = |bytes: &[u8]| {
+        for byte in bytes {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    };
+    for (id, pos, state) in robots {
+        feed(&id.to_le_bytes());
+        feed(&pos.x.to_bits().to_le_bytes());
+        feed(&pos.y.to_bits().to_le_bytes());
+        feed(&[state]);
+    }
+    hash
+}
@@ -0,0 +1,283 @@
+use bevy::prelude::*;
+use serde::Serialize;
+
+use crate::components::*;
+use crate::navigation::NavPath;
+use crate::resources::{NavigationMode, SimulationConfig};
+
+const MIN_PROGRESS: f32 = ROBOT_SIZE / 2.0; // distance a robot must close on its target within the window
+const LIVELOCK_SPEED: f32 = 0.1; // share of robot_speed a stuck cluster must average to count as a livelock
+const CLUSTER_WINDOW: f32 = 0.5; // share of the stall window after which a robot joins the cluster of a stuck neighbour
+
+/// How a group of robots that stopped getting closer to their targets is stuck.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum StallKind {
+    Blocked,  // a single robot, held up by a wall or by robots that aren't moving
+    Deadlock, // moving robots blocking each other, barely moving at all
+    Livelock, // moving robots pushing each other around without getting anywhere
+}
+
+/// What the watchdog did about a stall. Each repeat of a stall escalates to the next recovery.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Recovery {
+    Replan,                              // plan new paths from where the robots are
+    BackOff { leader: Option<RobotId> }, // back away (from the leader, who keeps right of way), then re-plan
+}
+
+/// Sent when the watchdog finds stuck robots, one message per cluster.
+#[derive(Message, Debug, Clone, PartialEq, Serialize)]
+pub struct StallDetected {
+    pub kind: StallKind,
+    pub robots: Vec<RobotId>, // in id order
+    pub position: (f32, f32), // centre of the cluster
+    pub recovery: Recovery,
+    pub sim_time: f32,
+}
+
+/// Progress a moving robot has made on its target, and the recovery it is in, if any.
+#[derive(Component, Debug, Clone, Default)]
+pub struct Stall {
+    target: Option<Vec3>,         // target progress is measured against, None while not moving
+    closest: f32,                 // distance to beat by MIN_PROGRESS
+    since: f32,                   // sim time the robot last made progress (or was last helped)
+    travelled: f32,               // distance driven since then
+    last_position: Vec3,
+    attempts: u32,                // recoveries tried since the robot last made progress
+    backoff: Option<(Vec3, f32)>, // direction to back away in, and the sim time to stop
+    priority_until: f32,          // sim time the robot keeps right of way until
+}
+
+impl Stall {
+    /// Direction to back away in, while backing off.
+    pub fn backing_off(&self) -> Option<Vec3> {
+        self.backoff.map(|(direction, _)| direction)
+    }
+
+    /// Whether other robots give way to this one, whatever their ids.
+    pub fn has_priority(&self, now: f32) -> bool {
+        now < self.priority_until
+    }
+
+    // Starts a new window from where the robot is now
+    fn restart(&mut self, distance: f32, position: Vec3, now: f32) {
+        self.closest = distance;
+        self.since = now;
+        self.travelled = 0.0;
+        self.last_position = position;
+    }
+}
+
+// --- SYSTEM ---
+
+type WatchedRobot = (Entity, &'static RobotId, &'static Transform, &'static TargetPosition, &'static RobotState, &'static mut Stall, &'static mut NavPath);
+
+/// Finds robots in a `Moving*` state that haven't got closer to their `TargetPosition` for
+/// `watchdog.stall_window` seconds, and gathers them into clusters with the robots within `collision_radius`
+/// that have been stalling for at least half as long. Reports each cluster as a `StallDetected` and starts
+/// a recovery. A cluster first re-plans; if it is still stuck a window later, one robot gets right of way
+/// (a different one each time) and the others back away from it.
+pub fn watchdog_system(
+    time: Res<Time>,
+    config: Res<SimulationConfig>,
+    mut stalls: MessageWriter<StallDetected>,
+    mut robot_query: Query<WatchedRobot, With<Robot>>,
+    mut stalling: Local<Vec<(RobotId, Entity, Vec3, bool)>> // and whether the full window has passed
+)
+{
+    let now = time.elapsed_secs();
+    let window = config.watchdog.stall_window;
+    stalling.clear();
+
+    // 1. Track each moving robot's progress
+    for (entity, id, transform, target, state, mut stall, mut path) in &mut robot_query {
+        let position = transform.translation;
+
+        // A robot done backing off needs a path from where it ended up
+        if stall.backoff.is_some_and(|(_, until)| now >= until) {
+            stall.backoff = None;
+            path.replan = true;
+        }
+
+        // Robots in line standing at their spot are waiting, not stuck
+        let distance = target.0.distance(position);
+        let standing_in_line = state.is_queued() && distance < config.state_change_radius;
+        if !state.is_moving() || standing_in_line {
+            stall.target = None;
+            stall.attempts = 0;
+            stall.backoff = None;
+            continue;
+        }
+
+        if stall.target != Some(target.0) {
+            stall.target = Some(target.0);
+            stall.attempts = 0;
+            stall.restart(distance, position, now);
+            continue;
+        }
+
+        stall.travelled += stall.last_position.distance(position);
+        stall.last_position = position;
+
+        if distance < stall.closest - MIN_PROGRESS {
+            stall.attempts = 0;
+            stall.restart(distance, position, now);
+        } else if now - stall.since >= window * CLUSTER_WINDOW && stall.backoff.is_none() {
+            stalling.push((*id, entity, position, now - stall.since >= window));
+        }
+    }
+    if !stalling.iter().any(|(.., stuck)| *stuck) { return; }
+
+    // 2. Grow a cluster from each stuck robot through the stalling ones close to it,
+    // in id order so clusters come out the same every run
+    stalling.sort_unstable_by_key(|(id, ..)| *id);
+    let mut cluster_of: Vec<Option<usize>> = vec![None; stalling.len()];
+    let mut clusters: Vec<Vec<usize>> = Vec::new();
+
+    for start in 0..stalling.len() {
+        if cluster_of[start].is_some() || !stalling[start].3 { continue; }
+        let index = clusters.len();
+        cluster_of[start] = Some(index);
+        let mut members = vec![start];
+        let mut next = 0;
+        while next < members.len() {
+            let position = stalling[members[next]].2;
+            next += 1;
+            for other in 0..stalling.len() {
+                if cluster_of[other].is_none() && stalling[other].2.distance(position) < config.collision_radius {
+                    cluster_of[other] = Some(index);
+                    members.push(other);
+                }
+            }
+        }
+        members.sort_unstable();
+        clusters.push(members);
+    }
+
+    // 3. Classify and recover
+    for members in clusters {
+        let robots: Vec<RobotId> = members.iter().map(|&i| stalling[i].0).collect();
+        let center = members.iter().map(|&i| stalling[i].2).sum::<Vec3>() / members.len() as f32;
+
+        let mut attempts = 0;
+        let mut speed = 0.0;
+        for &i in &members {
+            if let Ok((.., stall, _)) = robot_query.get(stalling[i].1) {
+                attempts = attempts.max(stall.attempts);
+                speed += stall.travelled / (now - stall.since).max(f32::EPSILON);
+            }
+        }
+        speed /= members.len() as f32;
+
+        let kind = if members.len() == 1 {
+            StallKind::Blocked
+        } else if speed < config.robot_speed * LIVELOCK_SPEED {
+            StallKind::Deadlock
+        } else {
+            StallKind::Livelock
+        };
+
+        // Cooperative robots keep to their schedules, so a new plan is all that helps them
+        let recovery = if attempts == 0 || config.navigation == NavigationMode::Cooperative {
+            Recovery::Replan
+        } else if members.len() == 1 {
+            Recovery::BackOff { leader: None }
+        } else {
+            Recovery::BackOff { leader: Some(robots[attempts as usize % robots.len()]) }
+        };
+        let leader_pos = match recovery {
+            Recovery::BackOff { leader: Some(leader) } => robots.iter().position(|id| *id == leader).map(|i| stalling[members[i]].2),
+            _ => None,
+        };
+
+        for &i in &members {
+            let (id, entity, position, _) = stalling[i];
+            let Ok((.., target, _, mut stall, mut path)) = robot_query.get_mut(entity) else { continue };
+
+            stall.attempts += 1;
+            stall.restart(target.0.distance(position), position, now);
+            path.replan = true;
+
+            if let Recovery::BackOff { leader } = recovery {
+                if leader == Some(id) {
+                    stall.priority_until = now + config.watchdog.backoff_time * 2.0;
+                } else {
+                    // Away from the leader, or from the target when stuck alone
+                    let away = (position - leader_pos.unwrap_or(target.0)).normalize_or_zero();
+                    stall.backoff = Some((away, now + config.watchdog.backoff_time));
+                }
+            }
+        }
+
+        stalls.write(StallDetected {
+            kind,
+            robots,
+            position: (center.x, center.y),
+            recovery,
+            sim_time: now,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::message::Messages;
+    use std::time::Duration;
+
+    // Two robots nose to nose, each heading for the other's side, and neither moving
+    fn head_on() -> (App, [Entity; 2]) {
+        let mut app = App::new();
+        app.insert_resource(SimulationConfig::for_tests())
+            .insert_resource(Time::<()>::default())
+            .add_message::<StallDetected>()
+            .add_systems(Update, watchdog_system);
+        let robots = [(0, -20.0, 200.0), (1, 20.0, -200.0)].map(|(id, x, target_x)| {
+            app.world_mut().spawn((
+                Robot,
+                RobotId(id),
+                Transform::from_xyz(x, 0.0, 0.0),
+                TargetPosition(Vec3::new(target_x, 0.0, 0.0)),
+                RobotState::MovingToPickup,
+                Stall::default(),
+                NavPath::default(),
+            )).id()
+        });
+        app.update();
+        (app, robots)
+    }
+
+    // Lets a stall window pass without anyone moving, and returns what the watchdog reported
+    fn wait_a_window(app: &mut App) -> Vec<StallDetected> {
+        let window = app.world().resource::<SimulationConfig>().watchdog.stall_window;
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs_f32(window + 0.1));
+        app.update();
+        app.world_mut().resource_mut::<Messages<StallDetected>>().drain().collect()
+    }
+
+    #[test]
+    fn head_on_robots_deadlock_then_one_gets_right_of_way() {
+        let (mut app, [first, second]) = head_on();
+
+        // First the pair re-plans
+        let stalls = wait_a_window(&mut app);
+        assert_eq!(stalls.len(), 1);
+        assert_eq!(stalls[0].kind, StallKind::Deadlock);
+        assert_eq!(stalls[0].robots, vec![RobotId(0), RobotId(1)]);
+        assert_eq!(stalls[0].position, (0.0, 0.0));
+        assert_eq!(stalls[0].recovery, Recovery::Replan);
+
+        // Still stuck a window later, so the second robot leads and the first backs away from it
+        let stalls = wait_a_window(&mut app);
+        assert_eq!(stalls.len(), 1);
+        assert_eq!(stalls[0].kind, StallKind::Deadlock);
+        assert_eq!(stalls[0].recovery, Recovery::BackOff { leader: Some(RobotId(1)) });
+
+        let now = app.world().resource::<Time>().elapsed_secs();
+        let leader = app.world().get::<Stall>(second).unwrap();
+        assert!(leader.has_priority(now));
+        assert_eq!(leader.backing_off(), None);
+        let follower = app.world().get::<Stall>(first).unwrap();
+        assert!(!follower.has_priority(now));
+        assert_eq!(follower.backing_off(), Some(Vec3::NEG_X));
+    }
+}
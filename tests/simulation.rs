@@ -0,0 +1,100 @@
+//! Runs the example scenario headless, one fixed tick per update, and checks what the robots did.
+
+use std::path::Path;
+
+use bevy::prelude::*;
+use bevy_ecs_sim::components::{Battery, Robot, RobotId, RobotState};
+use bevy_ecs_sim::config::load_config;
+use bevy_ecs_sim::metrics::Metrics;
+use bevy_ecs_sim::resources::{AvoidanceMode, OrderSource, SimulationConfig};
+use bevy_ecs_sim::utilityfunctions::state_checksum;
+use bevy_ecs_sim::SwarmSimPlugin;
+
+const TICKS_PER_SEC: usize = 60;
+
+fn scenario() -> SimulationConfig {
+    load_config(&Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/simulation.ron")).expect("example scenario loads")
+}
+
+fn run(config: SimulationConfig, seed: u64, ticks: usize) -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugins(SwarmSimPlugin::new(config).with_camera(false).with_seed(seed).deterministic(true));
+    for _ in 0..ticks {
+        app.update();
+    }
+    app
+}
+
+fn robots(app: &mut App) -> Vec<(RobotId, RobotState, f32)> {
+    let mut query = app.world_mut().query_filtered::<(&RobotId, &RobotState, &Battery), With<Robot>>();
+    let mut robots: Vec<_> = query.iter(app.world()).map(|(id, state, battery)| (*id, *state, battery.0)).collect();
+    robots.sort_by_key(|(id, ..)| *id);
+    robots
+}
+
+fn checksum(app: &mut App) -> u64 {
+    let mut query = app.world_mut().query_filtered::<(&RobotId, &Transform, &RobotState), With<Robot>>();
+    state_checksum(query.iter(app.world()).map(|(id, transform, state)| (id.0, transform.translation, *state as u8)))
+}
+
+#[test]
+fn robots_get_to_work() {
+    let config = scenario();
+    let robot_count = config.robot_count;
+    let mut app = run(config, 7, 30 * TICKS_PER_SEC);
+
+    let robots = robots(&mut app);
+    assert_eq!(robots.len(), robot_count);
+    assert!(robots.iter().all(|(.., battery)| *battery < 100.0), "every robot drains its battery");
+    assert!(robots.iter().all(|(_, state, _)| *state != RobotState::Dead), "no robot runs flat in 30 s");
+    assert!(robots.iter().any(|(_, state, _)| *state != RobotState::Idle), "some robot is working");
+
+    let metrics = app.world().resource::<Metrics>();
+    assert!(metrics.deliveries_completed > 0, "robots deliver within 30 s");
+    assert!((metrics.sim_time - 30.0).abs() < 0.1, "one fixed tick per update (sim time {})", metrics.sim_time);
+}
+
+#[test]
+fn same_seed_same_run() {
+    // Random order arrivals, so the seed has something to change
+    let config = SimulationConfig { orders: OrderSource::Poisson { per_hour: 3600.0 }, ..scenario() };
+    let ticks = 20 * TICKS_PER_SEC;
+    let first = checksum(&mut run(config.clone(), 42, ticks));
+    let second = checksum(&mut run(config.clone(), 42, ticks));
+    let other_seed = checksum(&mut run(config, 43, ticks));
+
+    assert_eq!(first, second, "the same scenario and seed end in the same state");
+    assert_ne!(first, other_seed, "a different seed takes a different run");
+}
+
+#[test]
+fn orca_keeps_robots_apart_where_separation_lets_them_touch() {
+    // Both models on the same scenario and seed
+    let ticks = 120 * TICKS_PER_SEC;
+    let [separation, orca] = [AvoidanceMode::Separation, AvoidanceMode::Orca].map(|avoidance| {
+        let app = run(SimulationConfig { avoidance, ..scenario() }, 0, ticks);
+        app.world().resource::<Metrics>().clone()
+    });
+
+    assert!(separation.deliveries_completed > 0 && orca.deliveries_completed > 0, "both models deliver");
+    assert!(orca.robot_collisions < separation.robot_collisions,
+        "ORCA has fewer robot collisions ({} against {})", orca.robot_collisions, separation.robot_collisions);
+    assert!(orca.deliveries_completed >= separation.deliveries_completed,
+        "ORCA delivers at least as much ({} against {})", orca.deliveries_completed, separation.deliveries_completed);
+}
+
+#[test]
+fn only_runs_following_the_wall_clock_are_sped_up() {
+    let config = SimulationConfig { time_scale: 30.0, ..scenario() };
+    let speed = |deterministic: bool| {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(SwarmSimPlugin::new(config.clone()).with_camera(false).deterministic(deterministic));
+        app.update();
+        app.world().resource::<Time<Virtual>>().relative_speed()
+    };
+
+    assert_eq!(speed(false), 30.0);
+    assert_eq!(speed(true), 1.0);
+}